@@ -0,0 +1,328 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use aoska::common::config::{ASM_ENDPOINT, ASM_INDEX_PATH, ASM_RECOMMEND_INDEX_PATH};
+use aoska::common::index::{Index, RecommendIndex};
+use aoska::common::oma::{check_tum_upgradable, check_upgradable, new_apt, TumUpdateInfo};
+use aoska::common::ops::{self, RunOptions};
+use aoska::common::packages::{Category, PackageBrief, PackageDetail, PackageFlags};
+use aoska::common::polkit::Subject;
+use aoska::common::sysroot::{self, SysrootError};
+use aoska::common::utils::{current_locale, fetch_data, localized};
+use aoska::common::{journal, omactl};
+use clap::{Parser, Subcommand};
+use oma_pm::apt::OmaOperation;
+use serde::Serialize;
+
+#[derive(Parser)]
+#[command(name = "aoska_cli")]
+#[command(about = "Browse the aoska catalog and manage packages without the GUI")]
+struct Cli {
+    /// Print JSON instead of human readable output
+    #[arg(long, global = true)]
+    json: bool,
+    /// Catalog endpoint
+    #[arg(long, global = true, default_value = ASM_ENDPOINT)]
+    endpoint: String,
+    /// Root of the system to manage, e.g. a container or chroot image
+    #[arg(long, global = true, default_value = "/", value_parser = parse_sysroot)]
+    sysroot: PathBuf,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// List the catalog, optionally only one category
+    Catalog {
+        #[arg(short, long)]
+        category: Option<Category>,
+    },
+    /// List recommended packages
+    Recommend,
+    /// Show the catalog detail of a package
+    Show { name: String },
+    /// Check for pending updates
+    Updates {
+        /// List topic updates instead of packages
+        #[arg(long)]
+        tum: bool,
+    },
+    /// Install packages from the catalog
+    Install {
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        /// Only show what would be done
+        #[arg(long)]
+        dry_run: bool,
+        /// Wait for the task to finish
+        #[arg(long)]
+        wait: bool,
+        /// Wait for the task and print its log to stderr
+        #[arg(long)]
+        follow: bool,
+        /// Wait for a running package operation for at most this many seconds
+        #[arg(long)]
+        lock_timeout: Option<u64>,
+    },
+}
+
+#[derive(Serialize)]
+struct InstallReport {
+    packages: Vec<PackageDetail>,
+    operation: OmaOperation,
+    /// None for a dry run.
+    unit: Option<String>,
+}
+
+fn parse_sysroot(path: &str) -> Result<PathBuf, SysrootError> {
+    sysroot::validate(Path::new(path))
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_briefs(packages: &[PackageBrief]) {
+    for p in packages {
+        println!("  {:<24} {}", p.name, p.intro);
+    }
+}
+
+fn flag_warnings(flags: &PackageFlags) -> Vec<&'static str> {
+    let mut warnings = Vec::new();
+    if flags.unoffical {
+        warnings.push("not provided by AOSC");
+    }
+    if !flags.verified {
+        warnings.push("not verified");
+    }
+    if flags.non_native {
+        warnings.push("not native to this architecture");
+    }
+    if flags.windows_app {
+        warnings.push("Windows application");
+    }
+    if flags.telemetry {
+        warnings.push("collects telemetry");
+    }
+    if flags.service_limited {
+        warnings.push("service may be limited in some regions");
+    }
+    warnings
+}
+
+fn print_detail(detail: &PackageDetail) {
+    let info = &detail.package_info;
+    println!("{} ({})", detail.title, detail.name);
+    println!("  {}", detail.intro);
+    println!("  Category:  {}", detail.category);
+    println!("  Version:   {}", info.version);
+    println!("  Publisher: {}", info.publisher);
+    println!("  Source:    {}", info.source);
+    println!("  Updated:   {}", info.update_date);
+    println!("  Size:      {}", human_size(info.install_size));
+    println!("  Homepage:  {}", info.homepage);
+    for w in flag_warnings(&detail.package_flags) {
+        println!("  Warning:   {w}");
+    }
+}
+
+fn print_operation(op: &OmaOperation) {
+    if op.install.is_empty() && op.remove.is_empty() {
+        println!("Nothing to do.");
+        return;
+    }
+    for p in &op.install {
+        println!("  install {} {}", p.name(), p.new_version());
+    }
+    for p in &op.remove {
+        println!("  remove  {}", p.name());
+    }
+    println!(
+        "{} to install/upgrade, {} to remove, {} to download",
+        op.install.len(),
+        op.remove.len(),
+        human_size(op.total_download_size as i64)
+    );
+}
+
+fn print_tum(updates: &[TumUpdateInfo]) {
+    if updates.is_empty() {
+        println!("No topic updates.");
+    }
+    let locale = current_locale();
+    for u in updates {
+        let name = localized(&u.name, &locale).unwrap_or(&u.manifest_name);
+        let security = if u.is_security { " [security]" } else { "" };
+        println!(
+            "  {:<24} {name}{security} ({} packages)",
+            u.manifest_name, u.package_count
+        );
+        if let Some(caution) = &u.caution_text {
+            println!("    Caution: {caution}");
+        }
+    }
+}
+
+fn human_size(bytes: i64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{prompt} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let client = reqwest::Client::builder().user_agent("aoska/1.0").build()?;
+    let endpoint = cli.endpoint.as_str();
+
+    match cli.command {
+        Commands::Catalog { category } => {
+            let index: Index = fetch_data(&client, endpoint, ASM_INDEX_PATH).await?;
+            let categories: Vec<_> = index
+                .packages
+                .into_iter()
+                .filter(|c| category.is_none_or(|cat| c.category == cat))
+                .collect();
+            if cli.json {
+                return print_json(&categories);
+            }
+            for c in &categories {
+                println!("{}:", c.category);
+                print_briefs(&c.packages);
+            }
+        }
+        Commands::Recommend => {
+            let recommend: RecommendIndex =
+                fetch_data(&client, endpoint, ASM_RECOMMEND_INDEX_PATH).await?;
+            if cli.json {
+                return print_json(&recommend);
+            }
+            print_briefs(&recommend.packages);
+        }
+        Commands::Show { name } => {
+            let path = format!("packages/{name}/meta.json");
+            let detail: PackageDetail = fetch_data(&client, endpoint, &path).await?;
+            if cli.json {
+                return print_json(&detail);
+            }
+            print_detail(&detail);
+        }
+        Commands::Updates { tum } => {
+            let apt = new_apt(&cli.sysroot, false)?;
+            if tum {
+                let updates = check_tum_upgradable(&apt, &cli.sysroot).await?;
+                if cli.json {
+                    return print_json(&updates);
+                }
+                print_tum(&updates);
+            } else {
+                let op = check_upgradable(&apt).await?;
+                if cli.json {
+                    return print_json(&op);
+                }
+                print_operation(&op);
+            }
+        }
+        Commands::Install {
+            packages,
+            yes,
+            dry_run,
+            wait,
+            follow,
+            lock_timeout,
+        } => {
+            omactl::validate_packages(&packages)?;
+            // only packages in the catalog, so their metadata can be reviewed first.
+            let mut details = Vec::new();
+            for name in &packages {
+                let path = format!("packages/{name}/meta.json");
+                match fetch_data::<PackageDetail>(&client, endpoint, &path).await {
+                    Ok(d) => details.push(d),
+                    Err(e) => bail!("{name} is not in the catalog: {e}"),
+                }
+            }
+            let operation = ops::preview_install(&packages, &cli.sysroot).await?;
+            if !cli.json {
+                details.iter().for_each(print_detail);
+                print_operation(&operation);
+            }
+
+            let unit = if dry_run {
+                None
+            } else {
+                if !yes {
+                    if cli.json || !io::stdin().is_terminal() {
+                        bail!("pass --yes to install non-interactively");
+                    }
+                    if !confirm("Install?")? {
+                        bail!("aborted");
+                    }
+                }
+                let subject = Subject::current()?;
+                // name the unit so its log can be printed from the start.
+                let unit = omactl::new_unit_name("install");
+                let (stop_tx, stop_rx) = mpsc::channel();
+                let printer = follow.then(|| {
+                    let unit = unit.clone();
+                    thread::spawn(move || {
+                        // stderr, stdout is for the report.
+                        journal::follow_unit(&unit, None, &stop_rx, |entry| {
+                            eprintln!("{}", entry.line)
+                        })
+                    })
+                });
+                let opts = RunOptions {
+                    wait: wait || follow,
+                    unit: Some(unit),
+                    lock_timeout: lock_timeout.map(Duration::from_secs),
+                    ..Default::default()
+                };
+                let started = ops::install(&packages, &cli.sysroot, &opts, &subject).await;
+                if let Some(printer) = printer {
+                    thread::sleep(journal::FLUSH_DELAY);
+                    let _ = stop_tx.send(());
+                    if let Ok(Err(e)) = printer.join() {
+                        eprintln!("failed to read journal: {e}");
+                    }
+                }
+                Some(started?)
+            };
+
+            if cli.json {
+                return print_json(&InstallReport {
+                    packages: details,
+                    operation,
+                    unit,
+                });
+            }
+            if let Some(unit) = unit {
+                println!("Started {unit}");
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,22 +1,34 @@
-use crate::common::omactl;
 use crate::common::{
+    apt_worker::APT_WORKER,
     config::{ASM_ENDPOINT, ASM_INDEX_PATH, ASM_RECOMMEND_INDEX_PATH},
+    deb::DebInfo,
+    history::{History, HistoryFilter, TaskRecord},
+    hold::{held_packages, set_hold},
     index::{CategoryIndex, Index, RecommendIndex},
-    oma::{check_tum_upgradable, check_upgradable, check_upgradable_count, TumUpdateInfo},
+    oma::{TumUpdateInfo, UpdateSummary},
+    ops::{self, RemovePreview, RunOptions, SecurityUpgradePreview, TopicUpgradePreview},
     packages::{Category, PackageDetail},
+    polkit::Subject,
+    repo_mgr::import_and_install,
+    safety::Confirmations,
+    scheduler::UpdateSchedule,
+    sysroot,
     utils::fetch_data,
 };
+use crate::common::{journal, omactl, progress, refresh};
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
-use std::process::{Command as StdCommand, Stdio};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 use tauri::Emitter; // windows.emit
 
-use oma_pm::apt::{AptConfig, OmaApt, OmaAptArgs, OmaOperation};
+use oma_pm::apt::OmaOperation;
 
 #[cfg(debug_assertions)]
 use httpmock::prelude::*;
@@ -156,73 +168,241 @@ pub async fn fetch_detail(
         .map_err(|e| e.to_string())
 }
 
+// The three below share one upgrade operation, cached by the apt worker
+// until the apt database changes.
 #[tauri::command]
 pub async fn fetch_update_count(_app: tauri::State<'_, AppState>) -> Result<usize, String> {
+    APT_WORKER
+        .upgrade_count(&sysroot::current())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn fetch_update_detail(
+    _app: tauri::State<'_, AppState>,
+) -> Result<Arc<OmaOperation>, String> {
+    APT_WORKER
+        .upgrade(&sysroot::current())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn fetch_tum_update(
+    _app: tauri::State<'_, AppState>,
+) -> Result<Vec<TumUpdateInfo>, String> {
+    APT_WORKER
+        .tum_updates(&sysroot::current())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Count, operation and topic updates for the update page in one go,
+/// plus the packages not covered by any topic.
+#[tauri::command]
+pub async fn fetch_update_summary(
+    _app: tauri::State<'_, AppState>,
+) -> Result<UpdateSummary, String> {
+    APT_WORKER
+        .summary(&sysroot::current())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Preview installing packages: the full operation with dependencies,
+/// download size and disk delta. Nothing is executed.
+#[tauri::command]
+pub async fn preview_install(packages: Vec<String>) -> Result<OmaOperation, String> {
+    ops::preview_install(&packages, &sysroot::current())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct RefreshMsg {
+    pub unit: String,
+    pub progress: refresh::MirrorProgress,
+}
+
+/// Refresh the package database and wait for it to finish.
+/// Lines about a mirror are emitted as "oma-refresh-progress".
+/// Payload JSON: { unit: String, progress: MirrorProgress }
+#[tauri::command]
+pub async fn refresh_database(
+    window: tauri::Window,
+    // wait for a running package operation for at most this many seconds.
+    lock_timeout: Option<u64>,
+) -> Result<refresh::RefreshReport, String> {
+    let subject = Subject::current().map_err(|e| e.to_string())?;
     tokio::task::spawn_blocking(move || {
-        let apt = OmaApt::new(
-            vec![],
-            OmaAptArgs::builder().build(),
-            false,
-            AptConfig::new(),
+        refresh::run_refresh(
+            &sysroot::current(),
+            lock_timeout.map(Duration::from_secs),
+            &subject,
+            move |unit, progress| {
+                let msg = RefreshMsg {
+                    unit: unit.to_string(),
+                    progress,
+                };
+                let _ = window.emit("oma-refresh-progress", msg);
+            },
         )
-        .map_err(|e| e.to_string())?;
-
-        // OmaApt type from the oma-pm contains fields that are not Send or Sync
-        // which means they cannot be safely shared between threads
-        // Use tokio runtime to run the async function in blocking context
-        let rt = tokio::runtime::Handle::current();
-        rt.block_on(async {
-            check_upgradable_count(&apt)
-                .await
-                .map_err(|e| e.to_string())
-        })
     })
     .await
-    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
 }
 
+/// Packages on hold, they are kept at their installed version when upgrading.
 #[tauri::command]
-pub async fn fetch_update_detail(_app: tauri::State<'_, AppState>) -> Result<OmaOperation, String> {
+pub async fn list_held_packages() -> Result<Vec<String>, String> {
+    held_packages(&sysroot::current())
+        .map(|held| held.into_iter().collect())
+        .map_err(|e| e.to_string())
+}
+
+/// Put packages on hold via omactl and wait for it, returning the unit name.
+#[tauri::command]
+pub async fn hold_packages(
+    packages: Vec<String>,
+    // wait for a running package operation for at most this many seconds.
+    lock_timeout: Option<u64>,
+) -> Result<String, String> {
+    omactl::validate_packages(&packages).map_err(|e| e.to_string())?;
     tokio::task::spawn_blocking(move || {
-        let apt = OmaApt::new(
-            vec![],
-            OmaAptArgs::builder().build(),
-            false,
-            AptConfig::new(),
+        set_hold(
+            &packages,
+            true,
+            &sysroot::current(),
+            lock_timeout.map(Duration::from_secs),
+            &Subject::current()?,
         )
-        .map_err(|e| e.to_string())?;
-
-        // OmaApt type from the oma-pm contains fields that are not Send or Sync
-        // which means they cannot be safely shared between threads
-        // Use tokio runtime to run the async function in blocking context
-        let rt = tokio::runtime::Handle::current();
-        rt.block_on(async { check_upgradable(&apt).await.map_err(|e| e.to_string()) })
     })
     .await
     .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
 }
 
+/// Take packages off hold, see `hold_packages`.
 #[tauri::command]
-pub async fn fetch_tum_update(
-    _app: tauri::State<'_, AppState>,
-) -> Result<Vec<TumUpdateInfo>, String> {
+pub async fn unhold_packages(
+    packages: Vec<String>,
+    // wait for a running package operation for at most this many seconds.
+    lock_timeout: Option<u64>,
+) -> Result<String, String> {
+    omactl::validate_packages(&packages).map_err(|e| e.to_string())?;
     tokio::task::spawn_blocking(move || {
-        let apt = OmaApt::new(
-            vec![],
-            OmaAptArgs::builder().build(),
+        set_hold(
+            &packages,
             false,
-            AptConfig::new(),
+            &sysroot::current(),
+            lock_timeout.map(Duration::from_secs),
+            &Subject::current()?,
         )
-        .map_err(|e| e.to_string())?;
-
-        // OmaApt type from the oma-pm contains fields that are not Send or Sync
-        // which means they cannot be safely shared between threads
-        // Use tokio runtime to run the async function in blocking context
-        let rt = tokio::runtime::Handle::current();
-        rt.block_on(async { check_tum_upgradable(&apt).await.map_err(|e| e.to_string()) })
     })
     .await
     .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+/// Time of the last successful refresh from aoska.
+#[tauri::command]
+pub async fn last_refresh_time() -> Result<Option<DateTime<Utc>>, String> {
+    Ok(refresh::last_refresh())
+}
+
+/// The background update check schedule, applied by the D-Bus service.
+#[tauri::command]
+pub async fn get_update_schedule() -> Result<UpdateSchedule, String> {
+    Ok(UpdateSchedule::load())
+}
+
+#[tauri::command]
+pub async fn set_update_schedule(schedule: UpdateSchedule) -> Result<(), String> {
+    schedule.save().map_err(|e| e.to_string())
+}
+
+// Confirmation tokens handed out by previews of destructive operations.
+static CONFIRMATIONS: Lazy<Mutex<Confirmations>> = Lazy::new(|| Mutex::new(Confirmations::new()));
+
+fn run_options(
+    wait: Option<bool>,
+    follow: Option<bool>,
+    unit: Option<String>,
+    assume_yes: Option<bool>,
+    lock_timeout: Option<u64>,
+) -> RunOptions {
+    RunOptions {
+        wait: wait.unwrap_or(false),
+        follow: follow.unwrap_or(false),
+        unit,
+        assume_yes: assume_yes.unwrap_or(true),
+        lock_timeout: lock_timeout.map(Duration::from_secs),
+    }
+}
+
+/// Preview removing packages, see `preview_install`.
+/// Fails if Essential or protected packages would be removed.
+#[tauri::command]
+pub async fn preview_remove(
+    packages: Vec<String>,
+    // purge, remove app config or not.
+    remove_config: Option<bool>,
+) -> Result<RemovePreview, String> {
+    ops::preview_remove(
+        &packages,
+        remove_config.unwrap_or(false),
+        &sysroot::current(),
+        &CONFIRMATIONS,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Preview upgrading only the packages of some topics, by `TumUpdateInfo.manifest_name`.
+#[tauri::command]
+pub async fn preview_topic_upgrade(
+    manifest_names: Vec<String>,
+) -> Result<TopicUpgradePreview, String> {
+    ops::preview_topic_upgrade(&manifest_names, &sysroot::current(), &CONFIRMATIONS)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Upgrade the packages of some topics via omactl, return the unit name.
+// token comes from a preview_topic_upgrade of the same topics.
+#[tauri::command]
+pub async fn start_topic_upgrade(
+    manifest_names: Vec<String>,
+    token: String,
+    // manifest names of the topics whose caution the user acknowledged.
+    acknowledged: Option<Vec<String>>,
+    wait: Option<bool>,
+    follow: Option<bool>,
+    unit: Option<String>,
+    assume_yes: Option<bool>,
+    // wait for a running package operation for at most this many seconds.
+    lock_timeout: Option<u64>,
+) -> Result<String, String> {
+    ops::topic_upgrade(
+        &manifest_names,
+        &token,
+        acknowledged.as_deref().unwrap_or_default(),
+        &sysroot::current(),
+        &CONFIRMATIONS,
+        &run_options(wait, follow, unit, assume_yes, lock_timeout),
+        &Subject::current().map_err(|e| e.to_string())?,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Preview the upgrade `start_upgrade` runs with `security_only`.
+#[tauri::command]
+pub async fn preview_security_upgrade() -> Result<SecurityUpgradePreview, String> {
+    ops::preview_security_upgrade(&sysroot::current())
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -233,29 +413,45 @@ pub async fn get_endpoint_base_url(app: tauri::State<'_, AppState>) -> Result<St
 // Report whether oma is currently busy.
 #[tauri::command]
 pub async fn oma_is_busy() -> Result<bool, String> {
-    Ok(omactl::is_busy())
+    Ok(omactl::is_busy(&sysroot::current()))
+}
+
+/// Report which process holds the oma/dpkg/apt lock, if any.
+#[tauri::command]
+pub async fn oma_lock_holder() -> Result<Option<omactl::LockHolder>, String> {
+    Ok(omactl::lock_holder(&sysroot::current()))
 }
 
 // Start a system upgrade via omactl, returning the systemd unit name.
+// Topics with a caution must be acknowledged: all of them unless security_only.
 #[tauri::command]
 pub async fn start_upgrade(
     packages: Option<Vec<String>>,
+    // only apply security updates, see preview_security_upgrade. packages must be empty.
+    security_only: Option<bool>,
+    // manifest names of the topics whose caution the user acknowledged.
+    acknowledged: Option<Vec<String>>,
     wait: Option<bool>,
     follow: Option<bool>,
     unit: Option<String>,
     assume_yes: Option<bool>,
+    // wait for a running package operation for at most this many seconds.
+    lock_timeout: Option<u64>,
 ) -> Result<String, String> {
-    let mut args: Vec<&str> = vec!["upgrade"];
-    if assume_yes.unwrap_or(true) {
-        args.push("--yes");
-    }
-    if let Some(pkgs) = &packages {
-        if !pkgs.is_empty() {
-            args.extend(pkgs.iter().map(|s| s.as_str()));
+    let packages = packages.unwrap_or_default();
+    let acknowledged = acknowledged.unwrap_or_default();
+    let root = sysroot::current();
+    let opts = run_options(wait, follow, unit, assume_yes, lock_timeout);
+    let subject = Subject::current().map_err(|e| e.to_string())?;
+    if security_only.unwrap_or(false) {
+        if !packages.is_empty() {
+            return Err("packages can't be given for a security-only upgrade".to_string());
         }
+        ops::security_upgrade(&acknowledged, &root, &opts, &subject).await
+    } else {
+        ops::upgrade(&packages, &acknowledged, &root, &opts, &subject).await
     }
-    omactl::run_oma(&args, wait.unwrap_or(false), follow.unwrap_or(false), unit.as_deref())
-        .map_err(|e| e.to_string())
+    .map_err(|e| e.to_string())
 }
 
 // Start installing packages via omactl, returning the systemd unit name.
@@ -267,50 +463,130 @@ pub async fn start_install(
     follow: Option<bool>,
     unit: Option<String>,
     assume_yes: Option<bool>,
+    // wait for a running package operation for at most this many seconds.
+    lock_timeout: Option<u64>,
 ) -> Result<String, String> {
-    if packages.is_empty() {
-        return Err("packages is empty".to_string());
-    }
-    let mut args: Vec<&str> = vec!["install"];
-    if assume_yes.unwrap_or(true) {
-        args.push("--yes");
-    }
-    let pkg_refs: Vec<&str> = packages.iter().map(|s| s.as_str()).collect();
-    args.extend(pkg_refs);
-    omactl::run_oma(&args,wait.unwrap_or(false), follow.unwrap_or(false), unit.as_deref())
-        .map_err(|e| e.to_string())
+    ops::install(
+        &packages,
+        &sysroot::current(),
+        &run_options(wait, follow, unit, assume_yes, lock_timeout),
+        &Subject::current().map_err(|e| e.to_string())?,
+    )
+    .await
+    .map_err(|e| e.to_string())
 }
 
 // Start removing packages via omactl, return the unit name.
+// token comes from a preview_remove of the same packages and remove_config.
 #[tauri::command]
 pub async fn start_remove(
     packages: Vec<String>,
+    token: String,
     // purge, remove app config or not.
     remove_config: Option<bool>,
     wait: Option<bool>,
     follow: Option<bool>,
     unit: Option<String>,
     assume_yes: Option<bool>,
+    // wait for a running package operation for at most this many seconds.
+    lock_timeout: Option<u64>,
 ) -> Result<String, String> {
-    if packages.is_empty() {
-        return Err("packages is empty".to_string());
-    }
-    let mut args: Vec<&str> = vec!["remove"];
-    if assume_yes.unwrap_or(true) {
-        args.push("--yes");
-    }
-    if remove_config.unwrap_or(true) {
-        args.push("--remove_config");
-    }
-    let pkg_refs: Vec<&str> = packages.iter().map(|s| s.as_str()).collect();
-    args.extend(pkg_refs);
-    omactl::run_oma(&args,wait.unwrap_or(false), follow.unwrap_or(false), unit.as_deref())
+    ops::remove(
+        &packages,
+        &token,
+        remove_config.unwrap_or(false),
+        &sysroot::current(),
+        &CONFIRMATIONS,
+        &run_options(wait, follow, unit, assume_yes, lock_timeout),
+        &Subject::current().map_err(|e| e.to_string())?,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+// deb file passed on the command line through the file association.
+static LAUNCH_DEB: Lazy<Mutex<Option<String>>> = Lazy::new(|| {
+    Mutex::new(
+        std::env::args()
+            .skip(1)
+            .find(|a| a.to_lowercase().ends_with(".deb")),
+    )
+});
+
+/// Take the deb file aoska was opened with from a file manager.
+/// Returns None if there is none or it has been taken already.
+#[tauri::command]
+pub async fn take_launch_deb() -> Result<Option<String>, String> {
+    Ok(LAUNCH_DEB.lock().unwrap().take())
+}
+
+/// Read the control metadata, maintainer scripts and file list of a local deb file.
+#[tauri::command]
+pub async fn inspect_local_deb(path: String) -> Result<DebInfo, String> {
+    tokio::task::spawn_blocking(move || DebInfo::read(&PathBuf::from(path)))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+// Install a local deb file via omactl, returning the unit name.
+#[tauri::command]
+pub async fn import_local_deb(
+    path: String,
+    wait: Option<bool>,
+    follow: Option<bool>,
+    unit: Option<String>,
+    assume_yes: Option<bool>,
+    // wait for a running package operation for at most this many seconds.
+    lock_timeout: Option<u64>,
+) -> Result<String, String> {
+    tokio::task::spawn_blocking(move || {
+        import_and_install(
+            PathBuf::from(path),
+            &sysroot::current(),
+            assume_yes.unwrap_or(true),
+            wait.unwrap_or(false),
+            follow.unwrap_or(false),
+            unit.as_deref(),
+            lock_timeout.map(Duration::from_secs),
+            &Subject::current()?,
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+/// List past install/remove/upgrade tasks started from aoska, newest first.
+#[tauri::command]
+pub async fn list_task_history(filter: Option<HistoryFilter>) -> Result<Vec<TaskRecord>, String> {
+    tokio::task::spawn_blocking(move || History::open()?.list(&filter.unwrap_or_default()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// Fetch a single task from the history by its unit name.
+#[tauri::command]
+pub async fn task_history_detail(unit: String) -> Result<TaskRecord, String> {
+    tokio::task::spawn_blocking(move || History::open()?.get(&unit))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// List oma task units with their state, see `oma_unit_status` for the details of one.
+#[tauri::command]
+pub async fn list_oma_units() -> Result<Vec<omactl::OmaUnit>, String> {
+    tokio::task::spawn_blocking(omactl::list_units)
+        .await
+        .map_err(|e| e.to_string())?
         .map_err(|e| e.to_string())
 }
 
 /// Fetch a unit's current status.
 #[tauri::command]
-pub async fn oma_unit_status(unit: String) -> Result<String, String> {
+pub async fn oma_unit_status(unit: String) -> Result<omactl::OmaUnit, String> {
     tokio::task::spawn_blocking(move || omactl::status(&unit)) // NOTE: use move to send result to another thread.
         .await
         .map_err(|e| e.to_string())?
@@ -319,7 +595,7 @@ pub async fn oma_unit_status(unit: String) -> Result<String, String> {
 
 /// Fetch a unit's accumulated logs.
 #[tauri::command]
-pub async fn oma_unit_logs(unit: String) -> Result<String, String> {
+pub async fn oma_unit_logs(unit: String) -> Result<Vec<String>, String> {
     tokio::task::spawn_blocking(move || omactl::logs(&unit))
         .await
         .map_err(|e| e.to_string())?
@@ -328,34 +604,80 @@ pub async fn oma_unit_logs(unit: String) -> Result<String, String> {
 
 /// Fetch a unit's result.
 #[tauri::command]
-pub async fn oma_unit_result(unit: String) -> Result<String, String> {
+pub async fn oma_unit_result(unit: String) -> Result<omactl::OmaUnitResult, String> {
     tokio::task::spawn_blocking(move || omactl::result(&unit))
         .await
         .map_err(|e| e.to_string())?
         .map_err(|e| e.to_string())
 }
 
-// Store active log-follow cancel senders so we can stop them.
+// Store active log-follow cancel senders so we can stop them,
+// with a generation telling apart successive followers of a unit.
 type StopSender = std::sync::mpsc::Sender<()>;
-type FollowerT = Arc<Mutex<HashMap<String, StopSender>>>;
+type FollowerT = Arc<Mutex<HashMap<String, (u64, StopSender)>>>;
+static NEXT_FOLLOWER: AtomicU64 = AtomicU64::new(0);
 static FOLLOWERS: Lazy<FollowerT> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
 
 fn followers_map() -> FollowerT {
     FOLLOWERS.clone()
 }
 
+// Keep the latest lines of each followed unit, so the frontend can catch up
+// after reattaching, and following can resume without duplicates.
+const LOG_BUFFER_LINES: usize = 2000;
+type LogBufferT = Arc<Mutex<HashMap<String, VecDeque<journal::JournalLine>>>>;
+static LOG_BUFFERS: Lazy<LogBufferT> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// Record a line in the unit's ring buffer.
+/// Returns false if it has been recorded already.
+fn buffer_line(unit: &str, line: &journal::JournalLine) -> bool {
+    let mut guard = LOG_BUFFERS.lock().unwrap();
+    let buf = guard.entry(unit.to_string()).or_default();
+    if buf.iter().rev().any(|l| l.cursor == line.cursor) {
+        return false;
+    }
+    if buf.len() >= LOG_BUFFER_LINES {
+        buf.pop_front();
+    }
+    buf.push_back(line.clone());
+    true
+}
+
+fn last_buffered_cursor(unit: &str) -> Option<String> {
+    let guard = LOG_BUFFERS.lock().unwrap();
+    guard.get(unit)?.back().map(|l| l.cursor.clone())
+}
+
 #[derive(serde::Serialize, Clone)]
 pub struct FollowerMsg {
     pub unit: String,
     pub line: String,
+    pub priority: Option<u8>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub cursor: Option<String>,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct ProgressMsg {
+    pub unit: String,
+    pub progress: progress::OmaProgress,
 }
 
 /// Start following a unit's logs and emit them to the frontend in real-time.
 /// Event name: "oma-log".
-/// Payload JSON: { unit: String, line: String }
+/// Payload JSON: { unit: String, line: String, priority: u8 | null, timestamp: String | null,
+///                 cursor: String | null }
+/// Lines carrying progress are also parsed and emitted as "oma-progress".
+/// Payload JSON: { unit: String, progress: OmaProgress }
+/// Following starts after `cursor` if given, otherwise after the last buffered line,
+/// otherwise from the unit's first line. Lines emitted before are in `oma_log_buffer`.
 /// If already (this wouldn't happen in design.) following the unit, returns Ok immediately.
 #[tauri::command]
-pub async fn follow_oma_logs(window: tauri::Window, unit: String) -> Result<(), String> {
+pub async fn follow_oma_logs(
+    window: tauri::Window,
+    unit: String,
+    cursor: Option<String>,
+) -> Result<(), String> {
     let map = followers_map();
     {
         let mut guard = map.lock().unwrap();
@@ -363,56 +685,108 @@ pub async fn follow_oma_logs(window: tauri::Window, unit: String) -> Result<(),
             return Ok(()); // already following, return.
         }
         let (tx, rx) = std::sync::mpsc::channel::<()>();
-        guard.insert(unit.clone(), tx); // record.
+        let generation = NEXT_FOLLOWER.fetch_add(1, Ordering::Relaxed);
+        guard.insert(unit.clone(), (generation, tx)); // record.
 
         let win = window.clone();
+        let after = cursor.or_else(|| last_buffered_cursor(&unit));
         thread::spawn(move || {
-            let mut cmd = StdCommand::new("journalctl");
-            cmd.args(["-u", &unit.clone(), "-f", "-o", "cat"])
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
-            if let Ok(mut child) = cmd.spawn() {
-                if let Some(stdout) = child.stdout.take() {
-                    let reader = BufReader::new(stdout);
-                    for line_res in reader.lines() {
-                        if rx.try_recv().is_ok() {
-                            break;
-                        }
-                        if let Ok(line) = line_res {
-                            let log_msg = FollowerMsg {
-                                unit: unit.clone(),
-                                line,
-                            };
-                            let _ = win.emit("oma-log", log_msg);
-                        } else {
-                            break;
-                        }
-                    }
+            let mut tracker = progress::ProgressTracker::new();
+            let res = journal::follow_unit(&unit, after.as_deref(), &rx, |entry| {
+                if !buffer_line(&unit, &entry) {
+                    return; // seen before the reconnect.
+                }
+                if let Some(progress) = tracker.feed(&entry.line) {
+                    let progress_msg = ProgressMsg {
+                        unit: unit.clone(),
+                        progress,
+                    };
+                    let _ = win.emit("oma-progress", progress_msg);
                 }
-                let _ = child.kill();
-            } else {
                 let log_msg = FollowerMsg {
                     unit: unit.clone(),
-                    line: "<failed to spwan journalctl>".to_string(),
+                    line: entry.line,
+                    priority: entry.priority,
+                    timestamp: entry.timestamp,
+                    cursor: Some(entry.cursor),
+                };
+                let _ = win.emit("oma-log", log_msg);
+            });
+            if let Err(e) = res {
+                let log_msg = FollowerMsg {
+                    unit: unit.clone(),
+                    line: format!("<failed to read journal: {e}>"),
+                    priority: None,
+                    timestamp: None,
+                    cursor: None,
                 };
                 let _ = win.emit("oma-log", log_msg);
             }
-            // remove the map from Followers
+            // remove the map from Followers, unless it was stopped and the unit
+            // is followed again: the entry is then the new follower's.
             let map = followers_map();
             let mut guard = map.lock().unwrap();
-            guard.remove(&unit.clone());
+            if guard.get(&unit).is_some_and(|(g, _)| *g == generation) {
+                guard.remove(&unit);
+            }
         });
     }
     Ok(())
 }
 
+/// Fetch the buffered lines of a unit, e.g. after the window reloaded.
+#[tauri::command]
+pub async fn oma_log_buffer(unit: String) -> Result<Vec<journal::JournalLine>, String> {
+    let guard = LOG_BUFFERS.lock().unwrap();
+    Ok(guard
+        .get(&unit)
+        .map(|buf| buf.iter().cloned().collect())
+        .unwrap_or_default())
+}
+
 /// Stop following a unit's logs.
 #[tauri::command]
 pub async fn stop_follow_oma_logs(unit: String) -> Result<(), String> {
+    stop_follower(&unit);
+    Ok(())
+}
+
+fn stop_follower(unit: &str) {
     let map = followers_map();
     let mut guard = map.lock().unwrap();
-    if let Some(sender) = guard.remove(&unit) {
+    if let Some((_, sender)) = guard.remove(unit) {
         let _ = sender.send(()); // signal stop
     }
-    Ok(())
+}
+
+/// Cancel a running unit, wait for it to stop (default 30s) and stop following its logs.
+/// The report tells whether dpkg was left in a consistent state.
+#[tauri::command]
+pub async fn cancel_oma_unit(
+    unit: String,
+    timeout: Option<u64>,
+) -> Result<omactl::CancelReport, String> {
+    let timeout = Duration::from_secs(timeout.unwrap_or(30));
+    let report = tokio::task::spawn_blocking({
+        let unit = unit.clone();
+        move || omactl::cancel_and_wait(&unit, timeout, &sysroot::current())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    stop_follower(&unit);
+    Ok(report)
+}
+
+/// The root of the system aoska manages, "/" for the running one.
+#[tauri::command]
+pub async fn get_sysroot() -> Result<PathBuf, String> {
+    Ok(sysroot::current())
+}
+
+/// Manage the system rooted at `path`, e.g. a container or chroot image.
+/// It must contain a dpkg database.
+#[tauri::command]
+pub async fn set_sysroot(path: String) -> Result<(), String> {
+    sysroot::set(PathBuf::from(path)).map_err(|e| e.to_string())
 }
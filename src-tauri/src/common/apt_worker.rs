@@ -0,0 +1,173 @@
+use crate::common::oma::{
+    check_upgrade, match_tum, new_apt, update_summary, TumUpdateInfo, UpdateSummary,
+};
+use crate::common::sysroot;
+use anyhow::{anyhow, Result};
+use oma_pm::apt::{OmaApt, OmaOperation};
+use once_cell::sync::Lazy;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::SystemTime;
+use tokio::sync::oneshot;
+
+/// Files rewritten by dpkg and apt when the package database changes, relative to the sysroot.
+const DB_PATHS: &[&str] = &[
+    "/var/lib/dpkg/status",
+    "/var/lib/apt/lists",
+    "/var/lib/apt/extended_states",
+];
+
+/// The worker shared by the GUI and the D-Bus service.
+pub static APT_WORKER: Lazy<AptWorker> = Lazy::new(AptWorker::spawn);
+
+type Reply<T> = oneshot::Sender<Result<T>>;
+
+// requests name the sysroot of the system to look at.
+enum AptRequest {
+    UpgradeCount(PathBuf, Reply<usize>),
+    Upgrade(PathBuf, Reply<Arc<OmaOperation>>),
+    Tum(PathBuf, Reply<Vec<TumUpdateInfo>>),
+    Summary(PathBuf, Reply<UpdateSummary>),
+    Invalidate,
+}
+
+/// Handle to a thread owning an `OmaApt`, which is not Send.
+/// The upgrade operation is computed once and reused until the apt database
+/// or the sysroot changes.
+pub struct AptWorker {
+    tx: Sender<AptRequest>,
+}
+
+impl AptWorker {
+    pub fn spawn() -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("apt-worker".to_string())
+            .spawn(move || Worker::default().run(rx))
+            .expect("spawn apt worker");
+        Self { tx }
+    }
+
+    /// Number of upgradable packages, held ones are not counted.
+    pub async fn upgrade_count(&self, sysroot: &Path) -> Result<usize> {
+        self.request(|r| AptRequest::UpgradeCount(sysroot.to_path_buf(), r))
+            .await
+    }
+
+    /// The full upgrade operation.
+    pub async fn upgrade(&self, sysroot: &Path) -> Result<Arc<OmaOperation>> {
+        self.request(|r| AptRequest::Upgrade(sysroot.to_path_buf(), r))
+            .await
+    }
+
+    /// Topic updates covered by the upgrade operation.
+    pub async fn tum_updates(&self, sysroot: &Path) -> Result<Vec<TumUpdateInfo>> {
+        self.request(|r| AptRequest::Tum(sysroot.to_path_buf(), r))
+            .await
+    }
+
+    /// Count, operation and topic updates at once.
+    pub async fn summary(&self, sysroot: &Path) -> Result<UpdateSummary> {
+        self.request(|r| AptRequest::Summary(sysroot.to_path_buf(), r))
+            .await
+    }
+
+    /// Drop the cached operation, e.g. after a change the file stamps would miss.
+    pub fn invalidate(&self) {
+        let _ = self.tx.send(AptRequest::Invalidate);
+    }
+
+    async fn request<T>(&self, make: impl FnOnce(Reply<T>) -> AptRequest) -> Result<T> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(make(reply))
+            .map_err(|_| anyhow!("apt worker has stopped"))?;
+        rx.await.map_err(|_| anyhow!("apt worker has stopped"))?
+    }
+}
+
+struct CachedUpgrade {
+    sysroot: PathBuf,
+    stamp: Vec<Option<SystemTime>>,
+    count: usize,
+    operation: Arc<OmaOperation>,
+    held: Vec<String>,
+}
+
+#[derive(Default)]
+struct Worker {
+    apt: Option<OmaApt>,
+    cached: Option<CachedUpgrade>,
+}
+
+impl Worker {
+    fn run(mut self, rx: Receiver<AptRequest>) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("apt worker runtime");
+        // a dropped reply means the caller has gone away, nothing to do about it.
+        for req in rx {
+            match req {
+                AptRequest::UpgradeCount(root, reply) => {
+                    let _ = reply.send(self.upgrade(&rt, root).map(|c| c.count));
+                }
+                AptRequest::Upgrade(root, reply) => {
+                    let _ = reply.send(self.upgrade(&rt, root).map(|c| c.operation.clone()));
+                }
+                AptRequest::Tum(root, reply) => {
+                    let _ = reply.send(
+                        self.upgrade(&rt, root)
+                            .and_then(|c| match_tum(&c.operation, &c.sysroot)),
+                    );
+                }
+                AptRequest::Summary(root, reply) => {
+                    let _ = reply.send(self.upgrade(&rt, root).and_then(|c| {
+                        update_summary(c.count, c.operation.clone(), c.held.clone(), &c.sysroot)
+                    }));
+                }
+                AptRequest::Invalidate => self.cached = None,
+            }
+        }
+    }
+
+    fn upgrade(
+        &mut self,
+        rt: &tokio::runtime::Runtime,
+        sysroot: PathBuf,
+    ) -> Result<&CachedUpgrade> {
+        let stamp = db_stamp(&sysroot);
+        if self
+            .cached
+            .as_ref()
+            .is_none_or(|c| c.sysroot != sysroot || c.stamp != stamp)
+        {
+            self.cached = None;
+            // apt reads the database once, a changed one needs a new instance.
+            let apt = self.apt.insert(new_apt(&sysroot, false)?);
+            let (count, operation, held) = rt.block_on(check_upgrade(apt, &sysroot))?;
+            self.cached = Some(CachedUpgrade {
+                sysroot,
+                stamp,
+                count,
+                operation: Arc::new(operation),
+                held,
+            });
+        }
+        Ok(self.cached.as_ref().expect("cached above"))
+    }
+}
+
+fn db_stamp(root: &Path) -> Vec<Option<SystemTime>> {
+    DB_PATHS
+        .iter()
+        .map(|p| {
+            fs::metadata(sysroot::join(root, p))
+                .and_then(|m| m.modified())
+                .ok()
+        })
+        .collect()
+}
@@ -3,3 +3,10 @@ pub const ASM_ENDPOINT: &str = "https:/raw.githubusercontent.com/AOSC-Dev/aosc-o
 pub const ASM_INDEX_PATH: &str = "aoska_index.json";
 pub const ASM_RECOMMEND_INDEX_PATH: &str = "recommend_index.json";
 pub const LOCAL_REPO_PATH: &str = "/opt/aoska/";
+// relative to the user's data directory, e.g. ~/.local/share
+pub const HISTORY_PATH: &str = "aoska/history.jsonl";
+pub const LAST_REFRESH_PATH: &str = "aoska/last_refresh";
+// relative to the user's cache directory, e.g. ~/.cache
+pub const DEB_STAGING_PATH: &str = "aoska/debs";
+// relative to the user's config directory, e.g. ~/.config
+pub const SCHEDULE_PATH: &str = "aoska/schedule.toml";
@@ -0,0 +1,358 @@
+use crate::common::apt_worker::APT_WORKER;
+use crate::common::hold::{held_packages, set_hold};
+use crate::common::omactl;
+use crate::common::ops::{self, RunOptions};
+use crate::common::polkit::{AuthError, Subject};
+use crate::common::refresh;
+use crate::common::repo_mgr::import_and_install;
+use crate::common::safety::Confirmations;
+use crate::common::sysroot;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use zbus::message::Header;
+use zbus::{connection, fdo, interface, Connection};
+
+pub const BUS_NAME: &str = "com.aosc.Aoska1";
+pub const OBJECT_PATH: &str = "/com/aosc/Aoska1";
+
+/// The aoska backend on D-Bus.
+/// Structured results are returned as JSON strings, in the same shape as the Tauri commands.
+#[derive(Default)]
+pub struct AoskaService {
+    confirmations: Mutex<Confirmations>,
+}
+
+#[interface(name = "com.aosc.Aoska1")]
+impl AoskaService {
+    async fn is_busy(&self) -> bool {
+        omactl::is_busy(&sysroot::current())
+    }
+
+    async fn update_count(&self) -> fdo::Result<u64> {
+        Ok(APT_WORKER
+            .upgrade_count(&sysroot::current())
+            .await
+            .map_err(failed)? as u64)
+    }
+
+    /// The upgrade operation as JSON.
+    async fn update_detail(&self) -> fdo::Result<String> {
+        let operation = APT_WORKER
+            .upgrade(&sysroot::current())
+            .await
+            .map_err(failed)?;
+        serde_json::to_string(&operation).map_err(failed)
+    }
+
+    /// Topic update manifests as JSON.
+    async fn tum_updates(&self) -> fdo::Result<String> {
+        let updates = APT_WORKER
+            .tum_updates(&sysroot::current())
+            .await
+            .map_err(failed)?;
+        serde_json::to_string(&updates).map_err(failed)
+    }
+
+    /// Count, operation, topic updates and packages not in any topic as JSON.
+    async fn update_summary(&self) -> fdo::Result<String> {
+        let summary = APT_WORKER
+            .summary(&sysroot::current())
+            .await
+            .map_err(failed)?;
+        serde_json::to_string(&summary).map_err(failed)
+    }
+
+    /// Refresh the package database and wait for it, returns the report as JSON.
+    async fn refresh(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<String> {
+        let subject = caller(conn, &header).await?;
+        let report = tokio::task::spawn_blocking(move || {
+            refresh::run_refresh(&sysroot::current(), None, &subject, |_, _| {})
+        })
+        .await
+        .map_err(failed)?;
+        serde_json::to_string(&report).map_err(failed)
+    }
+
+    /// Time of the last successful refresh in RFC 3339, empty if unknown.
+    async fn last_refresh(&self) -> String {
+        refresh::last_refresh()
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default()
+    }
+
+    /// The install operation as JSON, nothing is executed.
+    async fn preview_install(&self, packages: Vec<String>) -> fdo::Result<String> {
+        check_packages(&packages)?;
+        let operation = ops::preview_install(&packages, &sysroot::current())
+            .await
+            .map_err(failed)?;
+        serde_json::to_string(&operation).map_err(failed)
+    }
+
+    /// The remove operation as JSON, with the token `Remove` needs to confirm it.
+    async fn preview_remove(
+        &self,
+        packages: Vec<String>,
+        remove_config: bool,
+    ) -> fdo::Result<String> {
+        check_packages(&packages)?;
+        let preview = ops::preview_remove(
+            &packages,
+            remove_config,
+            &sysroot::current(),
+            &self.confirmations,
+        )
+        .await
+        .map_err(failed)?;
+        serde_json::to_string(&preview).map_err(failed)
+    }
+
+    /// Start installing packages, returns the unit name.
+    async fn install(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        packages: Vec<String>,
+    ) -> fdo::Result<String> {
+        check_packages(&packages)?;
+        let subject = caller(conn, &header).await?;
+        ops::install(
+            &packages,
+            &sysroot::current(),
+            &RunOptions::default(),
+            &subject,
+        )
+        .await
+        .map_err(denied_or_failed)
+    }
+
+    /// Start removing packages previewed by `PreviewRemove`, returns the unit name.
+    async fn remove(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        packages: Vec<String>,
+        token: String,
+        remove_config: bool,
+    ) -> fdo::Result<String> {
+        check_packages(&packages)?;
+        let subject = caller(conn, &header).await?;
+        ops::remove(
+            &packages,
+            &token,
+            remove_config,
+            &sysroot::current(),
+            &self.confirmations,
+            &RunOptions::default(),
+            &subject,
+        )
+        .await
+        .map_err(denied_or_failed)
+    }
+
+    /// Start upgrading `packages`, or the whole system if empty. Returns the unit name.
+    /// oma upgrades the whole system either way, so every topic with a caution
+    /// must be in `acknowledged`.
+    async fn upgrade(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        packages: Vec<String>,
+        acknowledged: Vec<String>,
+    ) -> fdo::Result<String> {
+        omactl::validate_packages(&packages).map_err(|e| fdo::Error::InvalidArgs(e.to_string()))?;
+        let subject = caller(conn, &header).await?;
+        ops::upgrade(
+            &packages,
+            &acknowledged,
+            &sysroot::current(),
+            &RunOptions::default(),
+            &subject,
+        )
+        .await
+        .map_err(denied_or_failed)
+    }
+
+    /// The operation upgrading only the packages of some topics as JSON,
+    /// with the token `TopicUpgrade` needs to confirm it.
+    async fn preview_topic_upgrade(&self, manifest_names: Vec<String>) -> fdo::Result<String> {
+        let preview =
+            ops::preview_topic_upgrade(&manifest_names, &sysroot::current(), &self.confirmations)
+                .await
+                .map_err(failed)?;
+        serde_json::to_string(&preview).map_err(failed)
+    }
+
+    /// Start upgrading the topics previewed by `PreviewTopicUpgrade`, returns the unit name.
+    /// Topics with a caution must be in `acknowledged`.
+    async fn topic_upgrade(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        manifest_names: Vec<String>,
+        token: String,
+        acknowledged: Vec<String>,
+    ) -> fdo::Result<String> {
+        let subject = caller(conn, &header).await?;
+        ops::topic_upgrade(
+            &manifest_names,
+            &token,
+            &acknowledged,
+            &sysroot::current(),
+            &self.confirmations,
+            &RunOptions::default(),
+            &subject,
+        )
+        .await
+        .map_err(denied_or_failed)
+    }
+
+    /// The security-only upgrade as JSON: security topics, their packages
+    /// plus those from security pockets, and the operation.
+    async fn preview_security_upgrade(&self) -> fdo::Result<String> {
+        let preview = ops::preview_security_upgrade(&sysroot::current())
+            .await
+            .map_err(failed)?;
+        serde_json::to_string(&preview).map_err(failed)
+    }
+
+    /// Start upgrading only the packages `PreviewSecurityUpgrade` shows, returns the unit name.
+    /// Topics with a caution must be in `acknowledged`.
+    async fn security_upgrade(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        acknowledged: Vec<String>,
+    ) -> fdo::Result<String> {
+        let subject = caller(conn, &header).await?;
+        ops::security_upgrade(
+            &acknowledged,
+            &sysroot::current(),
+            &RunOptions::default(),
+            &subject,
+        )
+        .await
+        .map_err(denied_or_failed)
+    }
+
+    /// Packages on hold, kept at their installed version when upgrading.
+    async fn held_packages(&self) -> fdo::Result<Vec<String>> {
+        held_packages(&sysroot::current())
+            .map(|held| held.into_iter().collect())
+            .map_err(failed)
+    }
+
+    /// Put packages on hold and wait for it, returns the unit name.
+    async fn hold(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        packages: Vec<String>,
+    ) -> fdo::Result<String> {
+        check_packages(&packages)?;
+        let subject = caller(conn, &header).await?;
+        tokio::task::spawn_blocking(move || {
+            set_hold(&packages, true, &sysroot::current(), None, &subject)
+        })
+        .await
+        .map_err(failed)?
+        .map_err(denied_or_failed)
+    }
+
+    /// Take packages off hold and wait for it, returns the unit name.
+    async fn unhold(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        packages: Vec<String>,
+    ) -> fdo::Result<String> {
+        check_packages(&packages)?;
+        let subject = caller(conn, &header).await?;
+        tokio::task::spawn_blocking(move || {
+            set_hold(&packages, false, &sysroot::current(), None, &subject)
+        })
+        .await
+        .map_err(failed)?
+        .map_err(denied_or_failed)
+    }
+
+    /// Install a local deb file, returns the unit name.
+    async fn import_deb(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        path: String,
+    ) -> fdo::Result<String> {
+        let subject = caller(conn, &header).await?;
+        tokio::task::spawn_blocking(move || {
+            import_and_install(
+                PathBuf::from(path),
+                &sysroot::current(),
+                true,
+                false,
+                false,
+                None,
+                None,
+                &subject,
+            )
+        })
+        .await
+        .map_err(failed)?
+        .map_err(denied_or_failed)
+    }
+}
+
+/// Serve aoska on the connection being built, e.g. one to a private bus for testing.
+pub async fn serve(builder: connection::Builder<'_>) -> zbus::Result<Connection> {
+    builder
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, AoskaService::default())?
+        .build()
+        .await
+}
+
+/// Serve aoska on the session bus until the process is killed.
+pub async fn run_session_service() -> zbus::Result<()> {
+    let _conn = serve(connection::Builder::session()?).await?;
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+fn failed(e: impl ToString) -> fdo::Error {
+    fdo::Error::Failed(e.to_string())
+}
+
+// polkit refusing is AccessDenied, so callers can tell it from a failed operation.
+fn denied_or_failed(e: anyhow::Error) -> fdo::Error {
+    match e.downcast_ref::<AuthError>() {
+        Some(e) => fdo::Error::AccessDenied(e.to_string()),
+        None => failed(e),
+    }
+}
+
+/// The process that sent the call being handled. polkit must authorize it,
+/// not the service, which would be allowed whatever the caller is.
+async fn caller(conn: &Connection, header: &Header<'_>) -> fdo::Result<Subject> {
+    let sender = header
+        .sender()
+        .ok_or_else(|| fdo::Error::AccessDenied("call without a sender".to_string()))?;
+    let credentials = fdo::DBusProxy::new(conn)
+        .await?
+        .get_connection_credentials(sender.clone().into())
+        .await?;
+    let pid = credentials
+        .process_id()
+        .ok_or_else(|| fdo::Error::AccessDenied(format!("unknown process for {sender}")))?;
+    Subject::process(pid).map_err(failed)
+}
+
+fn check_packages(packages: &[String]) -> fdo::Result<()> {
+    if packages.is_empty() {
+        return Err(fdo::Error::InvalidArgs("packages is empty".to_string()));
+    }
+    omactl::validate_packages(packages).map_err(|e| fdo::Error::InvalidArgs(e.to_string()))
+}
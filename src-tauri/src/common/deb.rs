@@ -0,0 +1,468 @@
+use ahash::{HashMap, HashMapExt};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DebError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("not a deb file: {0}")]
+    NotDeb(String),
+
+    #[error("unsupported compression: {0}")]
+    UnsupportedCompression(String),
+
+    #[error("invalid control file: {0}")]
+    Control(String),
+}
+
+/// Control metadata of a deb file.
+#[derive(Debug, Clone, Serialize)]
+pub struct DebControl {
+    pub package: String,
+    pub version: String,
+    pub architecture: String,
+    pub maintainer: Option<String>,
+    pub depends: Vec<String>,
+    /// Installed-Size field, in KiB.
+    pub installed_size: Option<u64>,
+    pub description: Option<String>,
+}
+
+/// Which maintainer scripts are shipped in control.tar.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MaintainerScripts {
+    pub preinst: bool,
+    pub postinst: bool,
+    pub prerm: bool,
+    pub postrm: bool,
+    pub config: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DebFileKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+/// An entry of data.tar.
+#[derive(Debug, Clone, Serialize)]
+pub struct DebFile {
+    /// Absolute path on the installed system.
+    pub path: String,
+    pub size: u64,
+    pub kind: DebFileKind,
+}
+
+/// Everything we know about a deb file, used to show "what will this install".
+#[derive(Debug, Clone, Serialize)]
+pub struct DebInfo {
+    pub control: DebControl,
+    pub conffiles: Vec<String>,
+    pub scripts: MaintainerScripts,
+    pub files: Vec<DebFile>,
+}
+
+impl DebControl {
+    /// Parse the content of a control file.
+    pub fn parse(control: &str) -> Result<Self, DebError> {
+        let mut fields = parse_fields(control);
+        let mut take = |key: &str| fields.remove(&key.to_ascii_lowercase());
+        let missing = |key: &str| DebError::Control(format!("missing {key} field"));
+
+        Ok(Self {
+            package: take("Package").ok_or_else(|| missing("Package"))?,
+            version: take("Version").ok_or_else(|| missing("Version"))?,
+            architecture: take("Architecture").ok_or_else(|| missing("Architecture"))?,
+            maintainer: take("Maintainer"),
+            depends: take("Depends")
+                .map(|d| {
+                    d.split(',')
+                        .map(|s| s.trim().to_string())
+                        .filter(|s| !s.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            installed_size: take("Installed-Size").and_then(|s| s.trim().parse().ok()),
+            description: take("Description"),
+        })
+    }
+}
+
+impl DebInfo {
+    /// Read control.tar and list data.tar of a deb file.
+    pub fn read(path: &Path) -> Result<Self, DebError> {
+        let mut control = None;
+        let mut files = Vec::new();
+        for_each_member(path, |name, reader| {
+            if name.starts_with("control.tar") {
+                control = Some(read_control_tar(decompress(name, reader)?)?);
+            } else if name.starts_with("data.tar") {
+                files = list_data_tar(decompress(name, reader)?)?;
+            }
+            Ok(true)
+        })?;
+
+        let (control, conffiles, scripts) =
+            control.ok_or_else(|| DebError::NotDeb("missing control.tar".to_string()))?;
+        Ok(Self {
+            control,
+            conffiles,
+            scripts,
+            files,
+        })
+    }
+}
+
+/// Read only the control metadata of a deb file, without walking data.tar.
+pub fn read_control(path: &Path) -> Result<DebControl, DebError> {
+    let mut control = None;
+    for_each_member(path, |name, reader| {
+        if name.starts_with("control.tar") {
+            let (c, ..) = read_control_tar(decompress(name, reader)?)?;
+            control = Some(c);
+            return Ok(false);
+        }
+        Ok(true)
+    })?;
+    control.ok_or_else(|| DebError::NotDeb("missing control.tar".to_string()))
+}
+
+/// Walk the ar members after debian-binary.
+/// Stops early when `f` returns false.
+fn for_each_member<F>(path: &Path, mut f: F) -> Result<(), DebError>
+where
+    F: FnMut(&str, &mut dyn Read) -> Result<bool, DebError>,
+{
+    let not_deb = || DebError::NotDeb(path.display().to_string());
+    let mut archive = ar::Archive::new(BufReader::new(File::open(path)?));
+
+    // the first member must be debian-binary with format version 2.x
+    let mut entry = archive.next_entry().ok_or_else(not_deb)??;
+    if entry.header().identifier() != b"debian-binary" {
+        return Err(not_deb());
+    }
+    let mut version = String::new();
+    entry.read_to_string(&mut version)?;
+    if !version.starts_with("2.") {
+        return Err(not_deb());
+    }
+    drop(entry);
+
+    while let Some(entry) = archive.next_entry() {
+        let mut entry = entry?;
+        let name = String::from_utf8_lossy(entry.header().identifier()).to_string();
+        // GNU ar may append a slash to member names.
+        let name = name.trim_end_matches('/');
+        if !f(name, &mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Wrap an ar member with the decompressor matching its extension.
+fn decompress<'a>(name: &str, reader: &'a mut dyn Read) -> Result<Box<dyn Read + 'a>, DebError> {
+    Ok(match name.rsplit_once(".tar").map(|(_, ext)| ext) {
+        Some("") => Box::new(reader),
+        Some(".gz") => Box::new(flate2::read::GzDecoder::new(reader)),
+        Some(".xz") => Box::new(liblzma::read::XzDecoder::new(reader)),
+        Some(".zst") => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        _ => return Err(DebError::UnsupportedCompression(name.to_string())),
+    })
+}
+
+fn read_control_tar(
+    reader: impl Read,
+) -> Result<(DebControl, Vec<String>, MaintainerScripts), DebError> {
+    let mut control = None;
+    let mut conffiles = Vec::new();
+    let mut scripts = MaintainerScripts::default();
+
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        match path.trim_start_matches("./") {
+            "control" => {
+                let mut s = String::new();
+                entry.read_to_string(&mut s)?;
+                control = Some(DebControl::parse(&s)?);
+            }
+            "conffiles" => {
+                let mut s = String::new();
+                entry.read_to_string(&mut s)?;
+                conffiles = s
+                    .lines()
+                    .map(|l| l.trim().to_string())
+                    .filter(|l| !l.is_empty())
+                    .collect();
+            }
+            "preinst" => scripts.preinst = true,
+            "postinst" => scripts.postinst = true,
+            "prerm" => scripts.prerm = true,
+            "postrm" => scripts.postrm = true,
+            "config" => scripts.config = true,
+            _ => {}
+        }
+    }
+
+    let control = control.ok_or_else(|| DebError::Control("missing control file".to_string()))?;
+    Ok((control, conffiles, scripts))
+}
+
+fn list_data_tar(reader: impl Read) -> Result<Vec<DebFile>, DebError> {
+    let mut files = Vec::new();
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        // "./usr/bin/foo" -> "/usr/bin/foo", skip the "./" root itself.
+        let path = format!(
+            "/{}",
+            path.strip_prefix('.').unwrap_or(&path).trim_matches('/')
+        );
+        if path == "/" {
+            continue;
+        }
+        let header = entry.header();
+        let kind = match header.entry_type() {
+            t if t.is_dir() => DebFileKind::Dir,
+            t if t.is_symlink() => DebFileKind::Symlink,
+            t if t.is_file() || t.is_hard_link() => DebFileKind::File,
+            _ => DebFileKind::Other,
+        };
+        files.push(DebFile {
+            path,
+            size: header.size()?,
+            kind,
+        });
+    }
+    Ok(files)
+}
+
+/// Split a deb822 paragraph into fields.
+/// Keys are lowercased since field names are case-insensitive,
+/// continuation lines are joined with '\n'.
+fn parse_fields(control: &str) -> HashMap<String, String> {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut current: Option<String> = None;
+    for line in control.lines() {
+        if line.starts_with([' ', '\t']) {
+            // continuation of the previous field.
+            if let Some(value) = current.as_ref().and_then(|k| fields.get_mut(k)) {
+                value.push('\n');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim().to_ascii_lowercase();
+            fields.insert(key.clone(), value.trim().to_string());
+            current = Some(key);
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    const CONTROL: &str = "\
+Package: hello
+Version: 2.10-1
+Architecture: amd64
+maintainer: Someone <someone@example.com>
+Depends: libc6 (>= 2.34), , zlib1g
+Installed-Size: 42
+Description: say hello
+ A longer description
+ over two lines.
+";
+
+    fn tar_of(entries: &[(&str, tar::EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, kind, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*kind);
+            header.set_mode(0o755);
+            header.set_size(data.len() as u64);
+            if kind.is_symlink() {
+                header.set_size(0);
+                builder
+                    .append_link(&mut header, path, std::str::from_utf8(data).unwrap())
+                    .unwrap();
+            } else {
+                builder.append_data(&mut header, path, *data).unwrap();
+            }
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(data).unwrap();
+        enc.finish().unwrap()
+    }
+
+    /// Write an ar archive of `members` to a temporary file.
+    fn write_ar(name: &str, members: &[(&str, Vec<u8>)]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("aoska-{}-{name}.deb", std::process::id()));
+        let mut builder = ar::Builder::new(File::create(&path).unwrap());
+        for (ident, data) in members {
+            let header = ar::Header::new(ident.as_bytes().to_vec(), data.len() as u64);
+            builder.append(&header, data.as_slice()).unwrap();
+        }
+        path
+    }
+
+    fn sample_deb(name: &str) -> PathBuf {
+        let control = tar_of(&[
+            ("./", tar::EntryType::Directory, b""),
+            ("./control", tar::EntryType::Regular, CONTROL.as_bytes()),
+            (
+                "./conffiles",
+                tar::EntryType::Regular,
+                b"/etc/hello.conf\n\n",
+            ),
+            ("./postinst", tar::EntryType::Regular, b"#!/bin/sh\n"),
+            ("./prerm", tar::EntryType::Regular, b"#!/bin/sh\n"),
+        ]);
+        let data = tar_of(&[
+            ("./", tar::EntryType::Directory, b""),
+            ("./usr/bin/", tar::EntryType::Directory, b""),
+            ("./usr/bin/hello", tar::EntryType::Regular, b"binary"),
+            ("./usr/bin/hi", tar::EntryType::Symlink, b"hello"),
+            ("./etc/hello.conf", tar::EntryType::Regular, b"x=1\n"),
+        ]);
+        write_ar(
+            name,
+            &[
+                ("debian-binary", b"2.0\n".to_vec()),
+                ("control.tar.gz", gzip(&control)),
+                ("data.tar", data),
+            ],
+        )
+    }
+
+    #[test]
+    fn parses_fields() {
+        let fields = parse_fields(CONTROL);
+        assert_eq!(fields["package"], "hello");
+        // keys are case-insensitive.
+        assert_eq!(fields["maintainer"], "Someone <someone@example.com>");
+        assert_eq!(
+            fields["description"],
+            "say hello\nA longer description\nover two lines."
+        );
+    }
+
+    #[test]
+    fn parses_control() {
+        let control = DebControl::parse(CONTROL).unwrap();
+        assert_eq!(control.package, "hello");
+        assert_eq!(control.version, "2.10-1");
+        assert_eq!(control.architecture, "amd64");
+        assert_eq!(control.depends, ["libc6 (>= 2.34)", "zlib1g"]);
+        assert_eq!(control.installed_size, Some(42));
+
+        let err = DebControl::parse("Package: hello\nArchitecture: all\n").unwrap_err();
+        assert!(err.to_string().contains("Version"), "{err}");
+    }
+
+    #[test]
+    fn decompresses_by_extension() {
+        let payload = b"payload".to_vec();
+        let zst = zstd::encode_all(payload.as_slice(), 0).unwrap();
+        for (name, data) in [
+            ("data.tar", payload.clone()),
+            ("data.tar.gz", gzip(&payload)),
+            ("data.tar.zst", zst),
+        ] {
+            let mut reader = data.as_slice();
+            let mut out = Vec::new();
+            decompress(name, &mut reader)
+                .unwrap()
+                .read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(out, payload, "{name}");
+        }
+        let mut reader: &[u8] = b"";
+        assert!(matches!(
+            decompress("data.tar.bz2", &mut reader),
+            Err(DebError::UnsupportedCompression(_))
+        ));
+    }
+
+    #[test]
+    fn lists_data_tar() {
+        let data = tar_of(&[
+            ("./", tar::EntryType::Directory, b""),
+            ("./usr/", tar::EntryType::Directory, b""),
+            ("./usr/bin/foo", tar::EntryType::Regular, b"12345"),
+            ("./usr/bin/bar", tar::EntryType::Symlink, b"foo"),
+        ]);
+        let files = list_data_tar(data.as_slice()).unwrap();
+        let got: Vec<(&str, u64, DebFileKind)> = files
+            .iter()
+            .map(|f| (f.path.as_str(), f.size, f.kind))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("/usr", 0, DebFileKind::Dir),
+                ("/usr/bin/foo", 5, DebFileKind::File),
+                ("/usr/bin/bar", 0, DebFileKind::Symlink),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_deb() {
+        let path = sample_deb("read");
+        let info = DebInfo::read(&path);
+        let control = read_control(&path);
+        fs::remove_file(&path).unwrap();
+
+        let info = info.unwrap();
+        assert_eq!(info.control.package, "hello");
+        assert_eq!(info.conffiles, ["/etc/hello.conf"]);
+        assert!(info.scripts.postinst && info.scripts.prerm);
+        assert!(!info.scripts.preinst && !info.scripts.postrm && !info.scripts.config);
+        let paths: Vec<&str> = info.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/usr/bin",
+                "/usr/bin/hello",
+                "/usr/bin/hi",
+                "/etc/hello.conf"
+            ]
+        );
+        assert_eq!(control.unwrap().version, "2.10-1");
+    }
+
+    #[test]
+    fn rejects_non_debs() {
+        let path = write_ar("notdeb", &[("data.tar", tar_of(&[]))]);
+        let res = read_control(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(res, Err(DebError::NotDeb(_))));
+
+        let path = write_ar("nocontrol", &[("debian-binary", b"2.0\n".to_vec())]);
+        let res = DebInfo::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(res, Err(DebError::NotDeb(_))));
+    }
+}
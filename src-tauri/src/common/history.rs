@@ -0,0 +1,332 @@
+use crate::common::config::HISTORY_PATH;
+use crate::common::omactl::{self, OmaOperationKind, UnitState};
+use crate::common::polkit::Subject;
+use ahash::{HashMap, HashMapExt};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+/// How many trailing log lines are kept with a finished task.
+const LOG_EXCERPT_LINES: usize = 20;
+
+/// A task omactl can't tell the state of after this long is given up on.
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("no data directory for the current user")]
+    NoDataDir,
+
+    #[error("task not found: {0}")]
+    NotFound(String),
+}
+
+/// A package operation started from aoska.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskRecord {
+    pub unit: String,
+    pub kind: OmaOperationKind,
+    pub packages: Vec<String>,
+    /// The user who started the task.
+    pub requester: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub result: UnitState,
+    pub exit_code: Option<i32>,
+    pub log_excerpt: Vec<String>,
+}
+
+impl TaskRecord {
+    /// A task started on behalf of the user `uid`.
+    pub fn new(kind: OmaOperationKind, packages: &[String], unit: &str, uid: u32) -> Self {
+        Self {
+            unit: unit.to_string(),
+            kind,
+            packages: packages.to_vec(),
+            requester: user_name(uid),
+            started_at: Utc::now(),
+            finished_at: None,
+            result: UnitState::Running,
+            exit_code: None,
+            log_excerpt: Vec::new(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished_at.is_some()
+    }
+
+    fn is_stale(&self) -> bool {
+        (Utc::now() - self.started_at)
+            .to_std()
+            .is_ok_and(|age| age >= STALE_AFTER)
+    }
+}
+
+/// Record a task started on behalf of `subject` in the history of the current user.
+/// Failing to do so must not fail the task, so errors are ignored.
+pub fn record_task(kind: OmaOperationKind, packages: &[String], unit: &str, subject: &Subject) {
+    if let Ok(history) = History::open() {
+        let _ = history.record(&TaskRecord::new(kind, packages, unit, subject.uid));
+    }
+}
+
+// The login name of `uid`, or the uid itself if it has none.
+fn user_name(uid: u32) -> String {
+    fs::read_to_string("/etc/passwd")
+        .ok()
+        .and_then(|passwd| passwd_name(&passwd, uid))
+        .unwrap_or_else(|| uid.to_string())
+}
+
+fn passwd_name(passwd: &str, uid: u32) -> Option<String> {
+    passwd.lines().find_map(|l| {
+        let mut fields = l.split(':');
+        let name = fields.next()?;
+        (fields.nth(1)?.parse() == Ok(uid)).then(|| name.to_string())
+    })
+}
+
+/// Conditions for listing history, all of them are optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HistoryFilter {
+    pub kind: Option<OmaOperationKind>,
+    /// Only tasks touching this package.
+    pub package: Option<String>,
+    pub result: Option<UnitState>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, r: &TaskRecord) -> bool {
+        self.kind.is_none_or(|k| r.kind == k)
+            && self
+                .package
+                .as_ref()
+                .is_none_or(|p| r.packages.iter().any(|x| x == p))
+            && self.result.is_none_or(|s| r.result == s)
+            && self.since.is_none_or(|t| r.started_at >= t)
+            && self.until.is_none_or(|t| r.started_at <= t)
+    }
+}
+
+/// Task history stored as JSON lines.
+/// Records are only appended, a later line for the same unit replaces the earlier one.
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    /// Open the history of the current user.
+    pub fn open() -> Result<Self, HistoryError> {
+        let path = dirs::data_dir()
+            .ok_or(HistoryError::NoDataDir)?
+            .join(HISTORY_PATH);
+        Ok(Self::with_path(path))
+    }
+
+    pub fn with_path(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn record(&self, record: &TaskRecord) -> Result<(), HistoryError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(f, "{}", serde_json::to_string(record)?)?;
+        Ok(())
+    }
+
+    /// Load all tasks, newest first.
+    pub fn load(&self) -> Result<Vec<TaskRecord>, HistoryError> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut records: HashMap<String, TaskRecord> = HashMap::new();
+        // skip lines we can't read instead of losing the whole history.
+        for r in content
+            .lines()
+            .filter_map(|l| serde_json::from_str::<TaskRecord>(l).ok())
+        {
+            records.insert(r.unit.clone(), r);
+        }
+        let mut records: Vec<TaskRecord> = records.into_values().collect();
+        records.sort_by_key(|r| std::cmp::Reverse(r.started_at));
+        Ok(records)
+    }
+
+    /// List tasks matching `filter`, newest first.
+    /// Tasks still running when last seen are refreshed from omactl first.
+    pub fn list(&self, filter: &HistoryFilter) -> Result<Vec<TaskRecord>, HistoryError> {
+        let records = self
+            .load()?
+            .into_iter()
+            .map(|r| self.refresh(r))
+            .filter(|r| filter.matches(r))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .collect();
+        Ok(records)
+    }
+
+    pub fn get(&self, unit: &str) -> Result<TaskRecord, HistoryError> {
+        let record = self
+            .load()?
+            .into_iter()
+            .find(|r| r.unit == unit)
+            .ok_or_else(|| HistoryError::NotFound(unit.to_string()))?;
+        Ok(self.refresh(record))
+    }
+
+    /// Fill in the result of a task which has finished since it was recorded.
+    /// Units omactl doesn't know anymore, or can't tell the state of for too long,
+    /// are finished as `Unknown` so they aren't asked about again.
+    fn refresh(&self, mut record: TaskRecord) -> TaskRecord {
+        if record.is_finished() {
+            return record;
+        }
+        match omactl::status(&record.unit) {
+            Ok(status) if status.state == UnitState::Running => return record,
+            Ok(status) if status.state == UnitState::Unknown && !record.is_stale() => {
+                return record
+            }
+            Ok(status) => {
+                record.result = status.state;
+                record.exit_code = status.exit_code;
+                record.finished_at = status.finished_at.or_else(|| Some(Utc::now()));
+                if let Ok(lines) = omactl::logs(&record.unit) {
+                    let skip = lines.len().saturating_sub(LOG_EXCERPT_LINES);
+                    record.log_excerpt = lines.into_iter().skip(skip).collect();
+                }
+            }
+            Err(_) => {
+                record.result = UnitState::Unknown;
+                record.finished_at = Some(Utc::now());
+            }
+        }
+        let _ = self.record(&record);
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn task(unit: &str, kind: OmaOperationKind, packages: &[&str], day: u32) -> TaskRecord {
+        let packages: Vec<String> = packages.iter().map(|p| p.to_string()).collect();
+        TaskRecord {
+            started_at: Utc.with_ymd_and_hms(2025, 6, day, 12, 0, 0).unwrap(),
+            ..TaskRecord::new(kind, &packages, unit, 0)
+        }
+    }
+
+    fn temp_history(name: &str) -> History {
+        let path = std::env::temp_dir().join(format!("aoska-{name}-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        History::with_path(path)
+    }
+
+    #[test]
+    fn filters_tasks() {
+        let r = task("u1", OmaOperationKind::Install, &["foo", "bar"], 10);
+        assert!(HistoryFilter::default().matches(&r));
+        let filter = |f: HistoryFilter| f.matches(&r);
+        assert!(filter(HistoryFilter {
+            kind: Some(OmaOperationKind::Install),
+            package: Some("bar".to_string()),
+            result: Some(UnitState::Running),
+            ..Default::default()
+        }));
+        assert!(!filter(HistoryFilter {
+            kind: Some(OmaOperationKind::Remove),
+            ..Default::default()
+        }));
+        assert!(!filter(HistoryFilter {
+            package: Some("baz".to_string()),
+            ..Default::default()
+        }));
+        assert!(!filter(HistoryFilter {
+            result: Some(UnitState::Failed),
+            ..Default::default()
+        }));
+        // since and until are inclusive.
+        assert!(filter(HistoryFilter {
+            since: Some(r.started_at),
+            until: Some(r.started_at),
+            ..Default::default()
+        }));
+        assert!(!filter(HistoryFilter {
+            since: Some(Utc.with_ymd_and_hms(2025, 6, 11, 0, 0, 0).unwrap()),
+            ..Default::default()
+        }));
+        assert!(!filter(HistoryFilter {
+            until: Some(Utc.with_ymd_and_hms(2025, 6, 9, 0, 0, 0).unwrap()),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn later_lines_win() {
+        let history = temp_history("load");
+        assert!(history.load().unwrap().is_empty());
+
+        let first = task("u1", OmaOperationKind::Install, &["foo"], 10);
+        let second = task("u2", OmaOperationKind::Remove, &["bar"], 11);
+        history.record(&first).unwrap();
+        history.record(&second).unwrap();
+        history
+            .record(&TaskRecord {
+                result: UnitState::Succeeded,
+                finished_at: Some(first.started_at),
+                ..first.clone()
+            })
+            .unwrap();
+        // a broken line doesn't lose the rest.
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&history.path)
+            .unwrap()
+            .write_all(b"{not json\n")
+            .unwrap();
+
+        let records = history.load().unwrap();
+        let units: Vec<&str> = records.iter().map(|r| r.unit.as_str()).collect();
+        assert_eq!(units, ["u2", "u1"]);
+        assert_eq!(records[1].result, UnitState::Succeeded);
+        assert!(records[1].is_finished());
+        let _ = fs::remove_file(&history.path);
+    }
+
+    #[test]
+    fn gives_up_on_old_tasks() {
+        assert!(task("u1", OmaOperationKind::Upgrade, &[], 10).is_stale());
+        assert!(!TaskRecord::new(OmaOperationKind::Upgrade, &[], "u2", 0).is_stale());
+    }
+
+    #[test]
+    fn names_requesters() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\nalice:x:1000:1000::/home/alice:/bin/sh\n";
+        assert_eq!(passwd_name(passwd, 1000).as_deref(), Some("alice"));
+        assert_eq!(passwd_name(passwd, 0).as_deref(), Some("root"));
+        assert_eq!(passwd_name(passwd, 1001), None);
+    }
+}
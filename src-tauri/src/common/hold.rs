@@ -0,0 +1,53 @@
+use crate::common::apt_worker::APT_WORKER;
+use crate::common::history::record_task;
+use crate::common::omactl::{self, OmaOperationKind};
+use crate::common::polkit::Subject;
+use crate::common::safety::DPKG_STATUS_PATH;
+use crate::common::sysroot;
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Packages on hold in the dpkg status file of the system at `root`.
+/// apt keeps them at their installed version when upgrading.
+pub fn held_packages(root: &Path) -> io::Result<BTreeSet<String>> {
+    let status = fs::read_to_string(sysroot::join(root, DPKG_STATUS_PATH))?;
+    Ok(status
+        .split("\n\n")
+        .filter(|p| {
+            p.lines()
+                .filter_map(|l| l.strip_prefix("Status: "))
+                .any(|s| s.starts_with("hold "))
+        })
+        .filter_map(|p| p.lines().find_map(|l| l.strip_prefix("Package: ")))
+        .map(|n| n.trim().to_string())
+        .collect())
+}
+
+/// Put packages of the system at `root` on hold, or take them off with `hold` false,
+/// via `oma mark` and wait for it, on behalf of `subject`. Returns the unit name.
+pub fn set_hold(
+    packages: &[String],
+    hold: bool,
+    root: &Path,
+    lock_timeout: Option<Duration>,
+    subject: &Subject,
+) -> Result<String> {
+    if packages.is_empty() {
+        anyhow::bail!("packages is empty");
+    }
+    let (action, kind) = if hold {
+        ("hold", OmaOperationKind::Hold)
+    } else {
+        ("unhold", OmaOperationKind::Unhold)
+    };
+    let mut args: Vec<&str> = vec!["mark", action];
+    args.extend(packages.iter().map(|s| s.as_str()));
+    let unit = omactl::run_oma(&args, true, false, None, lock_timeout, root, subject)?;
+    record_task(kind, packages, &unit, subject);
+    APT_WORKER.invalidate();
+    Ok(unit)
+}
@@ -0,0 +1,84 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use systemd::journal::{self, JournalSeek};
+
+/// How long to block waiting for new entries before checking the stop signal.
+const WAIT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long to keep reading the journal after a followed unit exited.
+pub const FLUSH_DELAY: Duration = Duration::from_millis(500);
+
+/// A journal entry of a followed unit.
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalLine {
+    pub line: String,
+    /// syslog priority, 0 (emerg) to 7 (debug).
+    pub priority: Option<u8>,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Journal cursor of this entry, resume following after it to avoid duplicates.
+    pub cursor: String,
+}
+
+/// Follow the journal of a unit, calling `on_line` for each entry.
+/// Starts from the first entry of the unit, or right after the `after` cursor.
+/// Returns once `stop` receives a signal or its sender is dropped.
+pub fn follow_unit<F>(
+    unit: &str,
+    after: Option<&str>,
+    stop: &Receiver<()>,
+    mut on_line: F,
+) -> Result<()>
+where
+    F: FnMut(JournalLine),
+{
+    let mut journal = journal::OpenOptions::default()
+        .system(true)
+        .local_only(true)
+        .open()?;
+    journal.match_add("_SYSTEMD_UNIT", service_name(unit))?;
+    match after {
+        Some(cursor) => journal.seek(JournalSeek::Cursor {
+            cursor: cursor.to_string(),
+        })?,
+        None => journal.seek(JournalSeek::Head)?,
+    };
+
+    loop {
+        match stop.try_recv() {
+            Ok(()) | Err(std::sync::mpsc::TryRecvError::Disconnected) => return Ok(()),
+            Err(std::sync::mpsc::TryRecvError::Empty) => {}
+        }
+
+        let Some(record) = journal.next_entry()? else {
+            // caught up, don't block forever so the stop signal is noticed.
+            journal.wait(Some(WAIT_INTERVAL))?;
+            continue;
+        };
+        let cursor = journal.cursor()?;
+        // seeking to a cursor lands on that entry, which has been seen already.
+        if after == Some(cursor.as_str()) {
+            continue;
+        }
+        let Some(line) = record.get("MESSAGE") else {
+            continue;
+        };
+        on_line(JournalLine {
+            line: line.clone(),
+            priority: record.get("PRIORITY").and_then(|p| p.parse().ok()),
+            timestamp: journal.timestamp().ok().map(DateTime::<Utc>::from),
+            cursor,
+        });
+    }
+}
+
+/// omactl reports unit names without the ".service" suffix.
+fn service_name(unit: &str) -> String {
+    if unit.contains('.') {
+        unit.to_string()
+    } else {
+        format!("{unit}.service")
+    }
+}
@@ -1,8 +1,21 @@
+pub mod apt_worker;
 pub mod config;
+pub mod dbus;
+pub mod deb;
+pub mod history;
+pub mod hold;
 pub mod index;
+pub mod journal;
 pub mod oma;
+pub mod ops;
 pub mod packages;
+pub mod polkit;
+pub mod progress;
+pub mod refresh;
 pub mod utils;
 pub mod omactl;
 pub mod repo_mgr;
+pub mod safety;
+pub mod scheduler;
+pub mod sysroot;
 pub mod download_mgr;
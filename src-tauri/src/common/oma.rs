@@ -1,10 +1,19 @@
-use ahash::HashMap;
+use crate::common::hold::held_packages;
+use crate::common::utils::{current_locale, localized};
+use ahash::{HashMap, HashSet};
 use anyhow::{Ok, Result};
 use oma_pm::{
-    apt::{OmaApt, OmaAptError, OmaOperation},
+    apt::{AptConfig, OmaApt, OmaAptArgs, OmaAptError, OmaOperation},
+    pkginfo::OmaPackage,
     sort::SummarySort,
 };
 use oma_tum::{get_matches_tum, get_tum};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Archives of security pockets end with this, like "stable-security".
+const SECURITY_POCKET_SUFFIX: &str = "security";
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct TumUpdateInfo {
@@ -14,6 +23,40 @@ pub struct TumUpdateInfo {
     pub package_count: usize,
     pub package_names: Vec<String>,
     pub caution: Option<HashMap<String, String>>,
+    /// `caution` in the current locale.
+    pub caution_text: Option<String>,
+    /// The topic has a caution the user must acknowledge before upgrading it.
+    pub requires_ack: bool,
+}
+
+/// An `OmaApt` for the system rooted at `sysroot`.
+pub fn new_apt(sysroot: &Path, dry_run: bool) -> Result<OmaApt, OmaAptError> {
+    OmaApt::new(
+        vec![],
+        OmaAptArgs::builder()
+            .sysroot(sysroot.to_string_lossy().to_string())
+            .build(),
+        dry_run,
+        AptConfig::new(),
+    )
+}
+
+/// Everything the update page shows, from a single upgrade operation.
+#[derive(Clone, serde::Serialize)]
+pub struct UpdateSummary {
+    pub count: usize,
+    pub operation: Arc<OmaOperation>,
+    pub tum: Vec<TumUpdateInfo>,
+    /// Packages of the operation not covered by any topic.
+    pub other: OtherUpdates,
+    /// Packages with an update which are on hold, left out of the operation.
+    pub held: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct OtherUpdates {
+    pub install: Vec<String>,
+    pub remove: Vec<String>,
 }
 
 pub async fn check_upgradable(apt: &OmaApt) -> Result<OmaOperation, OmaAptError> {
@@ -25,18 +68,216 @@ pub async fn check_upgradable(apt: &OmaApt) -> Result<OmaOperation, OmaAptError>
     )
 }
 
-pub async fn check_upgradable_count(apt: &OmaApt) -> Result<usize, OmaAptError> {
+/// Resolve installing `packages` without executing anything.
+pub async fn check_install(apt: &mut OmaApt, packages: &[String]) -> Result<OmaOperation> {
+    let pkgs = select_packages(apt, packages, true)?;
+    apt.install(&pkgs, false)?;
+    apt.resolve(false, false)?;
+    Ok(apt.summary(
+        SummarySort::default().operation().names(),
+        |_| false,
+        |_| false,
+    )?)
+}
+
+/// Resolve removing `packages` without executing anything.
+/// `purge` also removes their config files.
+pub async fn check_remove(
+    apt: &mut OmaApt,
+    packages: &[String],
+    purge: bool,
+) -> Result<OmaOperation> {
+    let pkgs = select_packages(apt, packages, false)?;
+    apt.remove(&pkgs, purge, false)?;
+    apt.resolve(false, false)?;
+    Ok(apt.summary(
+        SummarySort::default().operation().names(),
+        |_| false,
+        |_| false,
+    )?)
+}
+
+fn select_packages(
+    apt: &OmaApt,
+    packages: &[String],
+    filter_candidate: bool,
+) -> Result<Vec<OmaPackage>> {
+    let keywords: Vec<&str> = packages.iter().map(|s| s.as_str()).collect();
+    let (pkgs, not_found) = apt.select_pkg(&keywords, false, filter_candidate, false)?;
+    if !not_found.is_empty() {
+        anyhow::bail!("packages not found: {}", not_found.join(", "));
+    }
+    Ok(pkgs)
+}
+
+/// The number of upgradable packages, not counting held ones, and the upgrade as
+/// `check_upgradable` returns it, marking the upgrade only once.
+/// Also returns the held packages which have an update.
+pub async fn check_upgrade(
+    apt: &OmaApt,
+    sysroot: &Path,
+) -> Result<(usize, OmaOperation, Vec<String>), OmaAptError> {
     apt.upgrade(oma_pm::apt::Upgrade::FullUpgrade)?;
-    apt.count_pending_upgradable_pkgs()
+    let held = held_updates(apt, sysroot);
+    let count = apt
+        .count_pending_upgradable_pkgs()?
+        .saturating_sub(held.len());
+    let operation = apt.summary(
+        SummarySort::default().operation().names(),
+        |_| false,
+        |_| false,
+    )?;
+    Ok((count, operation, held))
 }
 
-pub async fn check_tum_upgradable(apt: &OmaApt) -> Result<Vec<TumUpdateInfo>> {
-    let sysroot = std::path::Path::new("/");
-    let tum_manifests = get_tum(sysroot)?;
+// apt keeps held packages back when upgrading, but still counts them as upgradable.
+fn held_updates(apt: &OmaApt, sysroot: &Path) -> Vec<String> {
+    held_packages(sysroot)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|n| apt.cache.get(n).is_some_and(|p| p.is_upgradable()))
+        .collect()
+}
+
+pub async fn check_tum_upgradable(apt: &OmaApt, sysroot: &Path) -> Result<Vec<TumUpdateInfo>> {
     let operation = check_upgradable(apt).await?;
+    match_tum(&operation, sysroot)
+}
+
+pub fn update_summary(
+    count: usize,
+    operation: Arc<OmaOperation>,
+    held: Vec<String>,
+    sysroot: &Path,
+) -> Result<UpdateSummary> {
+    let tum = match_tum(&operation, sysroot)?;
+    let covered: HashSet<&str> = tum
+        .iter()
+        .flat_map(|t| t.package_names.iter().map(|n| n.as_str()))
+        .collect();
+    let other = OtherUpdates {
+        install: operation
+            .install
+            .iter()
+            .map(|p| p.name())
+            .filter(|n| !covered.contains(n))
+            .map(|n| n.to_string())
+            .collect(),
+        remove: operation
+            .remove
+            .iter()
+            .map(|p| p.name())
+            .filter(|n| !covered.contains(n))
+            .map(|n| n.to_string())
+            .collect(),
+    };
+    Ok(UpdateSummary {
+        count,
+        operation,
+        tum,
+        other,
+        held,
+    })
+}
+
+/// The pending topics an upgrade touches: those with one of `installed` if it only
+/// installs these packages, all of them for `oma upgrade`, which upgrades everything
+/// whatever packages it is given.
+pub fn topics_in_upgrade<'a>(
+    tum: &'a [TumUpdateInfo],
+    installed: Option<&[String]>,
+) -> Vec<&'a TumUpdateInfo> {
+    tum.iter()
+        .filter(|t| installed.is_none_or(|p| t.package_names.iter().any(|n| p.contains(n))))
+        .collect()
+}
+
+/// The pending topics named by `manifest_names`,
+/// and the packages of the upgrade operation they cover.
+pub fn topic_packages(
+    summary: &UpdateSummary,
+    manifest_names: &[String],
+) -> Result<(Vec<TumUpdateInfo>, Vec<String>)> {
+    if manifest_names.is_empty() {
+        anyhow::bail!("no topics given");
+    }
+    let mut topics = Vec::new();
+    for manifest_name in manifest_names {
+        let topic = summary
+            .tum
+            .iter()
+            .find(|t| &t.manifest_name == manifest_name)
+            .ok_or_else(|| anyhow::anyhow!("topic {manifest_name} has no pending updates"))?;
+        topics.push(topic.clone());
+    }
+    let pending: HashSet<&str> = summary.operation.install.iter().map(|p| p.name()).collect();
+    let packages: BTreeSet<String> = topics
+        .iter()
+        .flat_map(|t| t.package_names.iter())
+        .filter(|n| pending.contains(n.as_str()))
+        .cloned()
+        .collect();
+    if packages.is_empty() {
+        anyhow::bail!("the topics have no packages to upgrade");
+    }
+    Ok((topics, packages.into_iter().collect()))
+}
+
+/// Resolve a security-only upgrade without executing anything: the packages of
+/// security topics plus those whose candidate comes from a security pocket.
+/// Returns the security topics, the packages to upgrade and the operation.
+pub async fn check_security_upgrade(
+    apt: &mut OmaApt,
+    summary: &UpdateSummary,
+) -> Result<(Vec<TumUpdateInfo>, Vec<String>, OmaOperation)> {
+    let topics: Vec<TumUpdateInfo> = summary
+        .tum
+        .iter()
+        .filter(|t| t.is_security)
+        .cloned()
+        .collect();
+    let in_topics: HashSet<&str> = topics
+        .iter()
+        .flat_map(|t| t.package_names.iter().map(|n| n.as_str()))
+        .collect();
+    let packages: Vec<String> = summary
+        .operation
+        .install
+        .iter()
+        .map(|p| p.name())
+        .filter(|n| in_topics.contains(n) || from_security_pocket(apt, n))
+        .map(|n| n.to_string())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    if packages.is_empty() {
+        anyhow::bail!("no security updates pending");
+    }
+    let operation = check_install(apt, &packages).await?;
+    Ok((topics, packages, operation))
+}
+
+fn from_security_pocket(apt: &OmaApt, name: &str) -> bool {
+    apt.cache
+        .get(name)
+        .and_then(|p| p.candidate())
+        .is_some_and(|v| {
+            v.package_files().any(|f| {
+                f.archive()
+                    .is_some_and(|a| a.ends_with(SECURITY_POCKET_SUFFIX))
+            })
+        })
+}
 
-    let matched_manifests = get_matches_tum(&tum_manifests, &operation);
+/// Topic updates covered by an upgrade operation.
+pub fn match_tum(operation: &OmaOperation, sysroot: &Path) -> Result<Vec<TumUpdateInfo>> {
+    let tum_manifests = get_tum(sysroot)?;
 
+    let matched_manifests = get_matches_tum(&tum_manifests, operation);
+    let locale = current_locale();
+
+    // constituent topics of each cumulative manifest.
+    let mut topics_of: HashMap<String, Vec<String>> = HashMap::default();
     let tum_update: Vec<TumUpdateInfo> = matched_manifests
         .into_iter()
         .map(|(manifest_name, entry_ref)| {
@@ -51,11 +292,23 @@ pub async fn check_tum_upgradable(apt: &OmaApt) -> Result<Vec<TumUpdateInfo>> {
                     caution,
                     name,
                 ),
-                // NOTE: Ignore Cumulative TUM since it hasn't been used yet.
-                oma_tum::TopicUpdateEntryRef::Cumulative { caution, name, .. } => {
+                // resolved from the constituent topics below.
+                oma_tum::TopicUpdateEntryRef::Cumulative {
+                    caution,
+                    name,
+                    topics,
+                    ..
+                } => {
+                    topics_of.insert(
+                        manifest_name.to_string(),
+                        topics.iter().map(|t| t.to_string()).collect(),
+                    );
                     (Vec::new(), caution, name)
                 }
             };
+            let caution_text = caution
+                .and_then(|c| localized(c, &locale))
+                .map(|s| s.to_string());
             TumUpdateInfo {
                 manifest_name: manifest_name.to_string(),
                 name: name.clone(),
@@ -63,8 +316,228 @@ pub async fn check_tum_upgradable(apt: &OmaApt) -> Result<Vec<TumUpdateInfo>> {
                 package_count: entry_ref.count_packages(),
                 package_names,
                 caution: caution.cloned(),
+                requires_ack: caution_text.is_some(),
+                caution_text,
             }
         })
         .collect();
-    Ok(tum_update)
+
+    // constituents come from every manifest, they need not be in the operation themselves.
+    let constituents: HashMap<String, Constituent> = tum_manifests
+        .iter()
+        .flat_map(|m| m.entries.iter())
+        .filter_map(|(manifest_name, entry)| match entry {
+            oma_tum::TopicUpdateEntry::Conventional {
+                security,
+                packages,
+                caution,
+                ..
+            } => Some((
+                manifest_name.to_string(),
+                Constituent {
+                    package_names: packages.keys().map(|name| name.to_string()).collect(),
+                    is_security: *security,
+                    caution_text: caution
+                        .as_ref()
+                        .and_then(|c| localized(c, &locale))
+                        .map(|s| s.to_string()),
+                },
+            )),
+            oma_tum::TopicUpdateEntry::Cumulative { .. } => None,
+        })
+        .collect();
+    Ok(resolve_cumulative(tum_update, &topics_of, &constituents))
+}
+
+/// What a cumulative update takes from one of its topics.
+#[derive(Debug, Clone, Default)]
+struct Constituent {
+    package_names: Vec<String>,
+    is_security: bool,
+    caution_text: Option<String>,
+}
+
+/// Fill in cumulative updates with the packages and cautions of their constituent topics.
+/// Constituent topics are then left out, they are shown as part of the cumulative update.
+/// Topics not in `constituents` add nothing, e.g. one dropped from the manifests.
+fn resolve_cumulative(
+    updates: Vec<TumUpdateInfo>,
+    topics_of: &HashMap<String, Vec<String>>,
+    constituents: &HashMap<String, Constituent>,
+) -> Vec<TumUpdateInfo> {
+    let superseded: HashSet<&str> = topics_of.values().flatten().map(|t| t.as_str()).collect();
+
+    let mut resolved = Vec::new();
+    for update in &updates {
+        if superseded.contains(update.manifest_name.as_str()) {
+            continue;
+        }
+        let mut update = update.clone();
+        if let Some(topics) = topics_of.get(&update.manifest_name) {
+            let mut names = BTreeSet::new();
+            let mut cautions = Vec::new();
+            for topic in topics.iter().filter_map(|t| constituents.get(t)) {
+                names.extend(topic.package_names.iter().cloned());
+                update.is_security |= topic.is_security;
+                update.requires_ack |= topic.caution_text.is_some();
+                cautions.extend(topic.caution_text.iter().cloned());
+            }
+            if update.caution_text.is_none() && !cautions.is_empty() {
+                update.caution_text = Some(cautions.join("\n\n"));
+            }
+            if !names.is_empty() {
+                update.package_names = names.into_iter().collect();
+                update.package_count = update.package_names.len();
+            }
+        }
+        resolved.push(update);
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(manifest_name: &str, packages: &[&str]) -> TumUpdateInfo {
+        TumUpdateInfo {
+            manifest_name: manifest_name.to_string(),
+            name: HashMap::default(),
+            is_security: false,
+            package_count: packages.len(),
+            package_names: packages.iter().map(|p| p.to_string()).collect(),
+            caution: None,
+            caution_text: None,
+            requires_ack: false,
+        }
+    }
+
+    fn constituent(packages: &[&str], is_security: bool, caution: Option<&str>) -> Constituent {
+        Constituent {
+            package_names: packages.iter().map(|p| p.to_string()).collect(),
+            is_security,
+            caution_text: caution.map(|c| c.to_string()),
+        }
+    }
+
+    fn topics_of(cumulative: &str, topics: &[&str]) -> HashMap<String, Vec<String>> {
+        let mut map = HashMap::default();
+        map.insert(
+            cumulative.to_string(),
+            topics.iter().map(|t| t.to_string()).collect(),
+        );
+        map
+    }
+
+    fn constituents(list: &[(&str, Constituent)]) -> HashMap<String, Constituent> {
+        list.iter()
+            .map(|(name, c)| (name.to_string(), c.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn merges_constituent_packages() {
+        let resolved = resolve_cumulative(
+            vec![update("survey-2025", &[])],
+            &topics_of("survey-2025", &["kde", "mesa"]),
+            &constituents(&[
+                ("kde", constituent(&["plasma", "kwin"], false, None)),
+                ("mesa", constituent(&["mesa", "kwin"], false, None)),
+            ]),
+        );
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].package_names, ["kwin", "mesa", "plasma"]);
+        assert_eq!(resolved[0].package_count, 3);
+        assert!(!resolved[0].is_security);
+        assert!(!resolved[0].requires_ack);
+    }
+
+    #[test]
+    fn leaves_out_superseded_topics() {
+        let resolved = resolve_cumulative(
+            vec![
+                update("kde", &["plasma"]),
+                update("survey-2025", &[]),
+                update("rust", &["rustc"]),
+            ],
+            &topics_of("survey-2025", &["kde"]),
+            &constituents(&[("kde", constituent(&["plasma"], false, None))]),
+        );
+        let names: Vec<&str> = resolved.iter().map(|u| u.manifest_name.as_str()).collect();
+        assert_eq!(names, ["survey-2025", "rust"]);
+    }
+
+    #[test]
+    fn propagates_security_and_cautions() {
+        let resolved = resolve_cumulative(
+            vec![update("survey-2025", &[])],
+            &topics_of("survey-2025", &["openssl", "kde", "glibc"]),
+            &constituents(&[
+                ("openssl", constituent(&["openssl"], true, None)),
+                (
+                    "kde",
+                    constituent(&["plasma"], false, Some("Log out first.")),
+                ),
+                (
+                    "glibc",
+                    constituent(&["glibc"], false, Some("Reboot after.")),
+                ),
+            ]),
+        );
+        assert!(resolved[0].is_security);
+        assert!(resolved[0].requires_ack);
+        assert_eq!(
+            resolved[0].caution_text.as_deref(),
+            Some("Log out first.\n\nReboot after.")
+        );
+
+        // its own caution wins over those of its topics.
+        let mut own = update("survey-2025", &[]);
+        own.caution_text = Some("Read the notes.".to_string());
+        own.requires_ack = true;
+        let resolved = resolve_cumulative(
+            vec![own],
+            &topics_of("survey-2025", &["kde"]),
+            &constituents(&[(
+                "kde",
+                constituent(&["plasma"], false, Some("Log out first.")),
+            )]),
+        );
+        assert_eq!(resolved[0].caution_text.as_deref(), Some("Read the notes."));
+    }
+
+    #[test]
+    fn resolves_topics_outside_the_operation() {
+        // "kde" is not among the matched updates, only in the manifests.
+        let resolved = resolve_cumulative(
+            vec![update("survey-2025", &[])],
+            &topics_of("survey-2025", &["kde"]),
+            &constituents(&[("kde", constituent(&["plasma"], false, None))]),
+        );
+        assert_eq!(resolved[0].package_names, ["plasma"]);
+    }
+
+    #[test]
+    fn skips_missing_constituents() {
+        let resolved = resolve_cumulative(
+            vec![update("survey-2025", &[])],
+            &topics_of("survey-2025", &["kde", "gone"]),
+            &constituents(&[("kde", constituent(&["plasma"], false, None))]),
+        );
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].package_names, ["plasma"]);
+        assert_eq!(resolved[0].package_count, 1);
+    }
+
+    #[test]
+    fn full_upgrades_touch_every_topic() {
+        let tum = [update("kde", &["plasma"]), update("rust", &["rustc"])];
+        let names = |topics: Vec<&TumUpdateInfo>| -> Vec<String> {
+            topics.iter().map(|t| t.manifest_name.clone()).collect()
+        };
+        let packages = ["plasma".to_string()];
+        assert_eq!(names(topics_in_upgrade(&tum, Some(&packages))), ["kde"]);
+        // `oma upgrade plasma` upgrades rustc as well.
+        assert_eq!(names(topics_in_upgrade(&tum, None)), ["kde", "rust"]);
+    }
 }
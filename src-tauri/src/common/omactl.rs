@@ -1,30 +1,266 @@
+use crate::common::polkit::{self, PolkitAction, Subject};
+use crate::common::repo_mgr::staging_dir;
+use crate::common::sysroot;
 use crate::common::utils::run_cmd;
+use ahash::HashMap;
 use anyhow::Result;
-use std::path::Path;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::BuildHasher;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path};
 use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use strum_macros::{Display, EnumString};
 use thiserror::Error;
 
+/// Locks taken by oma, dpkg and apt, relative to the sysroot. Any of them
+/// being held means a package operation is in progress.
+const LOCK_PATHS: &[&str] = &[
+    "/run/lock/oma.lock",
+    "/var/lib/dpkg/lock-frontend",
+    "/var/lib/dpkg/lock",
+    "/var/lib/apt/lists/lock",
+    "/var/cache/apt/archives/lock",
+];
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// oma subcommands aoska runs, with the flags it may pass to each.
+const ALLOWED_ARGS: &[(&str, &[&str])] = &[
+    ("install", &["--yes"]),
+    ("remove", &["--yes", "--remove-config"]),
+    ("upgrade", &["--yes"]),
+    ("refresh", &[]),
+    ("mark", &[]),
+];
+
+/// Actions of `oma mark` aoska may run.
+const MARK_ACTIONS: &[&str] = &["hold", "unhold"];
+
 #[derive(Error, Debug)]
-#[error("oma is busy {0}")]
+#[error("oma is busy{0}")]
 pub struct OmaBusyError(String);
 
 impl OmaBusyError {
-    pub fn new(unit: Option<&str>) -> Self {
-        let s = unit.map(|u| format!(" (unit={u})")).unwrap_or_default();
+    pub fn new(unit: Option<&str>, holder: Option<&LockHolder>) -> Self {
+        let mut s = unit.map(|u| format!(" (unit={u})")).unwrap_or_default();
+        if let Some(h) = holder {
+            s.push_str(&format!(
+                ": {} is held by pid {} ({})",
+                h.lock,
+                h.pid,
+                h.command.as_deref().unwrap_or("unknown")
+            ));
+        }
         Self(s)
     }
 }
 
-pub fn is_busy() -> bool {
-    let lock_path = "/run/lock/oma.lock";
-    Path::new(lock_path).exists()
+#[derive(Error, Debug)]
+#[error("invalid oma arguments: {0}")]
+pub struct InvalidArgsError(String);
+
+/// Check the subcommand and flags against `ALLOWED_ARGS` before handing them to oma.
+pub fn validate_args(args: &[&str]) -> Result<(), InvalidArgsError> {
+    let (subcommand, rest) = args
+        .split_first()
+        .ok_or_else(|| InvalidArgsError("no subcommand".to_string()))?;
+    let flags = ALLOWED_ARGS
+        .iter()
+        .find_map(|(cmd, flags)| (cmd == subcommand).then_some(*flags))
+        .ok_or_else(|| InvalidArgsError(format!("unknown subcommand {subcommand}")))?;
+    if let Some(flag) = rest
+        .iter()
+        .find(|a| a.starts_with('-') && !flags.contains(a))
+    {
+        return Err(InvalidArgsError(format!(
+            "unknown flag {flag} for {subcommand}"
+        )));
+    }
+    if *subcommand == "mark" && !rest.first().is_some_and(|a| MARK_ACTIONS.contains(a)) {
+        return Err(InvalidArgsError(format!(
+            "mark needs one of {}",
+            MARK_ACTIONS.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("invalid package name {0:?}")]
+pub struct InvalidPackageError(String);
+
+/// Validate a package argument: `name[:arch][=version]`.
+/// name and version follow Debian policy, arch is lowercase alphanumeric or dashes.
+/// Rejects anything oma could take as an option or a path.
+pub fn validate_package(spec: &str) -> Result<(), InvalidPackageError> {
+    let err = || InvalidPackageError(spec.to_string());
+    let (rest, version) = match spec.split_once('=') {
+        Some((rest, version)) => (rest, Some(version)),
+        None => (spec, None),
+    };
+    let (name, arch) = match rest.split_once(':') {
+        Some((name, arch)) => (name, Some(arch)),
+        None => (rest, None),
+    };
+
+    // Policy 5.6.1: at least two characters, starts with an alphanumeric.
+    let name_ok = name.len() >= 2
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c));
+    let arch_ok = arch.is_none_or(|a| {
+        a.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+            && a.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    });
+    // Policy 5.6.12: [epoch:]upstream[-revision], upstream starts with a digit.
+    let version_ok = version.is_none_or(|v| {
+        let upstream = v.split_once(':').map_or(v, |(_, u)| u);
+        upstream.starts_with(|c: char| c.is_ascii_digit())
+            && v.chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".+~:-".contains(c))
+    });
+
+    if name_ok && arch_ok && version_ok {
+        Ok(())
+    } else {
+        Err(err())
+    }
+}
+
+pub fn validate_packages<S: AsRef<str>>(packages: &[S]) -> Result<(), InvalidPackageError> {
+    packages
+        .iter()
+        .try_for_each(|p| validate_package(p.as_ref()))
+}
+
+/// A deb staged in `dir` by `import_local_deb`.
+fn is_local_deb(arg: &str, dir: &Path) -> bool {
+    let path = Path::new(arg);
+    path.starts_with(dir)
+        && arg.ends_with(".deb")
+        && path
+            .components()
+            .all(|c| matches!(c, Component::RootDir | Component::Normal(_)))
+}
+
+/// The process currently holding a package manager lock.
+#[derive(Debug, Clone, Serialize)]
+pub struct LockHolder {
+    pub lock: String,
+    pub pid: u32,
+    pub command: Option<String>,
+}
+
+/// Find who holds one of the package manager locks of the system at `root`, if any.
+/// A stale lock file left by a dead process does not count.
+pub fn lock_holder(root: &Path) -> Option<LockHolder> {
+    // /proc/locks lists both flock (oma) and fcntl (dpkg/apt) locks with the holder pid.
+    let locks = fs::read_to_string("/proc/locks").ok()?;
+    LOCK_PATHS.iter().find_map(|lock| {
+        let lock = sysroot::join(root, lock);
+        let meta = fs::metadata(&lock).ok()?;
+        let pid = find_lock_pid(&locks, meta.dev(), meta.ino())?;
+        let proc_dir = Path::new("/proc").join(pid.to_string());
+        if !proc_dir.exists() {
+            return None;
+        }
+        let command = fs::read_to_string(proc_dir.join("comm"))
+            .ok()
+            .map(|s| s.trim().to_string());
+        Some(LockHolder {
+            lock: lock.to_string_lossy().to_string(),
+            pid,
+            command,
+        })
+    })
+}
+
+pub fn is_busy(root: &Path) -> bool {
+    lock_holder(root).is_some()
+}
+
+/// Block until all package manager locks are free or `timeout` elapsed.
+pub fn wait_until_free(
+    timeout: Duration,
+    unit: Option<&str>,
+    root: &Path,
+) -> Result<(), OmaBusyError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let Some(holder) = lock_holder(root) else {
+            return Ok(());
+        };
+        if Instant::now() >= deadline {
+            return Err(OmaBusyError::new(unit, Some(&holder)));
+        }
+        thread::sleep(LOCK_POLL_INTERVAL);
+    }
+}
+
+/// Look up the pid holding a lock on (dev, ino) in the content of /proc/locks.
+/// Line format: `1: FLOCK  ADVISORY  WRITE 1234 00:19:5678 0 EOF`,
+/// waiters are marked with `->` and skipped.
+fn find_lock_pid(locks: &str, dev: u64, ino: u64) -> Option<u32> {
+    // same encoding as glibc's major()/minor().
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+
+    locks.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) == Some(&"->") || fields.len() < 6 {
+            return None;
+        }
+        let mut id = fields[5].split(':');
+        let maj = u64::from_str_radix(id.next()?, 16).ok()?;
+        let min = u64::from_str_radix(id.next()?, 16).ok()?;
+        let inode = id.next()?.parse::<u64>().ok()?;
+        if (maj, min, inode) != (major, minor, ino) {
+            return None;
+        }
+        fields[4].parse().ok()
+    })
 }
 
 /// Run an oma task via omactl. Returns the unit name created by omactl.
-pub fn run_oma(args: &[&str], wait: bool, follow: bool, unit: Option<&str>) -> Result<String> {
-    // TODO: race condition ? there must be a better solution.
-    if is_busy() {
-        return Err(OmaBusyError::new(unit).into());
+/// With `lock_timeout`, wait for a running package operation to finish
+/// instead of failing with `OmaBusyError` right away.
+/// oma works on the system rooted at `root`, on behalf of `subject`,
+/// the process polkit authorizes.
+pub fn run_oma(
+    args: &[&str],
+    wait: bool,
+    follow: bool,
+    unit: Option<&str>,
+    lock_timeout: Option<Duration>,
+    root: &Path,
+    subject: &Subject,
+) -> Result<String> {
+    validate_args(args)?;
+    for arg in args.iter().skip(1).filter(|a| !a.starts_with('-')) {
+        let staged = staging_dir().is_some_and(|dir| is_local_deb(arg, &dir));
+        if !(args[0] == "install" && staged) {
+            validate_package(arg)?;
+        }
+    }
+    if let Some(action) = PolkitAction::for_oma(args[0]) {
+        polkit::authorize(action, subject)?;
+    }
+    // NOTE: the lock could still be taken between this check and omactl starting oma,
+    // in that case oma itself refuses to run and the unit fails.
+    match lock_timeout {
+        Some(timeout) => wait_until_free(timeout, unit, root)?,
+        None => {
+            if let Some(holder) = lock_holder(root) {
+                return Err(OmaBusyError::new(unit, Some(&holder)).into());
+            }
+        }
     }
     let mut cmd = Command::new("omactl");
     cmd.arg("run");
@@ -38,49 +274,260 @@ pub fn run_oma(args: &[&str], wait: bool, follow: bool, unit: Option<&str>) -> R
         cmd.arg(format!("--unit={u}"));
     }
     cmd.arg("--");
+    if !sysroot::is_host(root) {
+        cmd.arg("--sysroot").arg(root);
+    }
     cmd.args(args);
 
     let out = run_cmd(cmd)?;
     // unit=oma-task-YYYYmmddHHMMSS-<rand>
-    let unit = out
+    let reported = out
         .lines()
         .find_map(|l| l.strip_prefix("unit="))
-        .unwrap_or("")
-        .trim()
-        .to_string();
-    Ok(if unit.is_empty() { out } else { unit })
+        .map(|u| u.trim().to_string())
+        .filter(|u| !u.is_empty());
+    // with --follow the output is the task log, never pass it off as the unit.
+    match reported.or_else(|| unit.map(str::to_string)) {
+        Some(unit) => Ok(unit),
+        None => anyhow::bail!("omactl did not report the unit name:\n{out}"),
+    }
 }
 
-pub fn list_units() -> Result<String> {
-    run_cmd({
+/// A unit name in omactl's format for `task`, to name a unit
+/// whose log is followed from the start.
+pub fn new_unit_name(task: &str) -> String {
+    // random like omactl's own, so units started within the same second don't collide,
+    // e.g. a refresh from the window and one from the scheduler.
+    let rand = RandomState::new().hash_one(Instant::now()) as u32;
+    format!(
+        "oma-task-{}-{rand:08x}-{task}",
+        Local::now().format("%Y%m%d%H%M%S")
+    )
+}
+
+/// State of a unit, accepts both omactl's and systemd's wording.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, EnumString)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum UnitState {
+    #[strum(serialize = "running", serialize = "active", serialize = "activating")]
+    Running,
+    #[strum(serialize = "succeeded", serialize = "success", serialize = "inactive")]
+    Succeeded,
+    Failed,
+    #[strum(serialize = "cancelled", serialize = "canceled")]
+    Cancelled,
+    Unknown,
+}
+
+/// The oma subcommand a unit runs.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, EnumString, Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum OmaOperationKind {
+    Install,
+    Remove,
+    Upgrade,
+    Refresh,
+    Hold,
+    Unhold,
+    Other,
+}
+
+/// Status of an oma task unit.
+#[derive(Debug, Clone, Serialize)]
+pub struct OmaUnit {
+    pub unit: String,
+    pub state: UnitState,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub exit_code: Option<i32>,
+    pub operation: Option<OmaOperationKind>,
+    pub packages: Vec<String>,
+}
+
+/// Final result of an oma task unit.
+#[derive(Debug, Clone, Serialize)]
+pub struct OmaUnitResult {
+    pub unit: String,
+    pub state: UnitState,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+}
+
+impl OmaUnit {
+    /// Parse `omactl status` output.
+    /// omactl prints `key=value` lines, the same way `run` reports `unit=`:
+    /// unit, state, started_at, finished_at, exit_code and command.
+    pub fn parse(unit: &str, out: &str) -> Self {
+        let kv = parse_kv(out);
+        let (operation, packages) = kv
+            .get("command")
+            .map(|c| parse_command(c))
+            .unwrap_or_default();
+
+        Self {
+            unit: kv
+                .get("unit")
+                .map(String::as_str)
+                .unwrap_or(unit)
+                .to_string(),
+            state: parse_state(kv.get("state").map(String::as_str)),
+            started_at: kv
+                .get("started_at")
+                .and_then(|t| parse_time(t))
+                .or_else(|| time_from_unit_name(unit)),
+            finished_at: kv.get("finished_at").and_then(|t| parse_time(t)),
+            exit_code: kv.get("exit_code").and_then(|c| c.parse().ok()),
+            operation,
+            packages,
+        }
+    }
+
+    /// Parse `omactl list` output: one unit per line, its name first and its state second.
+    /// Other lines, like a header, are skipped.
+    pub fn parse_list(out: &str) -> Vec<Self> {
+        out.lines()
+            .filter_map(|l| {
+                let mut columns = l.split_whitespace();
+                let unit = columns.next().filter(|u| u.starts_with("oma-task-"))?;
+                Some(Self {
+                    unit: unit.to_string(),
+                    state: parse_state(columns.next()),
+                    started_at: time_from_unit_name(unit),
+                    finished_at: None,
+                    exit_code: None,
+                    operation: None,
+                    packages: Vec::new(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl OmaUnitResult {
+    /// Parse `omactl result` output, which has `result` and `exit_code` keys.
+    pub fn parse(unit: &str, out: &str) -> Self {
+        let kv = parse_kv(out);
+        let state = parse_state(
+            kv.get("result")
+                .or_else(|| kv.get("state"))
+                .map(String::as_str),
+        );
+        let exit_code = kv.get("exit_code").and_then(|c| c.parse().ok());
+
+        Self {
+            unit: kv
+                .get("unit")
+                .map(String::as_str)
+                .unwrap_or(unit)
+                .to_string(),
+            state,
+            success: state == UnitState::Succeeded && exit_code.unwrap_or(0) == 0,
+            exit_code,
+        }
+    }
+}
+
+fn parse_kv(out: &str) -> HashMap<String, String> {
+    out.lines()
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect()
+}
+
+fn parse_state(state: Option<&str>) -> UnitState {
+    state
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(UnitState::Unknown)
+}
+
+/// Split an oma command line into its subcommand and package arguments.
+/// e.g. `oma install --yes foo bar` -> (Install, [foo, bar]),
+/// `oma mark hold foo` -> (Hold, [foo])
+fn parse_command(command: &str) -> (Option<OmaOperationKind>, Vec<String>) {
+    let mut words = command
+        .split_whitespace()
+        .skip_while(|w| *w == "oma" || w.ends_with("/oma") || *w == "--");
+    let mut args = Vec::new();
+    while let Some(w) = words.next() {
+        if w == "--sysroot" {
+            words.next(); // its path.
+        } else if !w.starts_with('-') {
+            args.push(w);
+        }
+    }
+    let mut args = args.into_iter();
+    let operation = args.next().map(|op| {
+        let op = if op == "mark" {
+            args.next().unwrap_or(op)
+        } else {
+            op
+        };
+        op.parse().unwrap_or(OmaOperationKind::Other)
+    });
+    (operation, args.map(str::to_string).collect())
+}
+
+/// Accept RFC 3339 and `YYYY-mm-dd HH:MM:SS` in local time.
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t.with_timezone(&Utc));
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok()?;
+    local_to_utc(naive)
+}
+
+/// unit=oma-task-YYYYmmddHHMMSS-<rand>
+fn time_from_unit_name(unit: &str) -> Option<DateTime<Utc>> {
+    let ts = unit.strip_prefix("oma-task-")?.split('-').next()?;
+    let naive = NaiveDateTime::parse_from_str(ts, "%Y%m%d%H%M%S").ok()?;
+    local_to_utc(naive)
+}
+
+fn local_to_utc(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&naive)
+        .single()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// List oma task units known to omactl with their state, from a single `omactl list`.
+/// The command and exit code are only in `status`, ask it for the units which need them.
+pub fn list_units() -> Result<Vec<OmaUnit>> {
+    let out = run_cmd({
         let mut c = Command::new("omactl");
         c.arg("list");
         c
-    })
+    })?;
+    Ok(OmaUnit::parse_list(&out))
 }
 
-pub fn status(unit: &str) -> Result<String> {
-    run_cmd({
+pub fn status(unit: &str) -> Result<OmaUnit> {
+    let out = run_cmd({
         let mut c = Command::new("omactl");
         c.arg("status").arg(unit);
         c
-    })
+    })?;
+    Ok(OmaUnit::parse(unit, &out))
 }
 
-pub fn logs(unit: &str) -> Result<String> {
-    run_cmd({
+pub fn logs(unit: &str) -> Result<Vec<String>> {
+    let out = run_cmd({
         let mut c = Command::new("omactl");
         c.arg("logs").arg(unit);
         c
-    })
+    })?;
+    Ok(out.lines().map(str::to_string).collect())
 }
 
-pub fn result(unit: &str) -> Result<String> {
-    run_cmd({
+pub fn result(unit: &str) -> Result<OmaUnitResult> {
+    let out = run_cmd({
         let mut c = Command::new("omactl");
         c.arg("result").arg(unit);
         c
-    })
+    })?;
+    Ok(OmaUnitResult::parse(unit, &out))
 }
 
 pub fn cancel(unit: &str) -> Result<String> {
@@ -90,3 +537,311 @@ pub fn cancel(unit: &str) -> Result<String> {
         c
     })
 }
+
+/// What is left after cancelling a unit.
+#[derive(Debug, Clone, Serialize)]
+pub struct CancelReport {
+    pub unit: OmaUnit,
+    /// Whether the unit stopped running before the timeout.
+    pub stopped: bool,
+    /// Whether `dpkg --audit` found nothing to fix.
+    pub dpkg_consistent: bool,
+    /// Problems reported by `dpkg --audit`, if any.
+    pub dpkg_audit: Vec<String>,
+}
+
+/// Cancel a unit and wait for at most `timeout` for it to stop,
+/// then check whether dpkg of the system at `root` was interrupted in the middle of something.
+pub fn cancel_and_wait(unit: &str, timeout: Duration, root: &Path) -> Result<CancelReport> {
+    cancel(unit)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut current = status(unit)?;
+    while current.state == UnitState::Running && Instant::now() < deadline {
+        thread::sleep(LOCK_POLL_INTERVAL);
+        current = status(unit)?;
+    }
+    let stopped = current.state != UnitState::Running;
+    let dpkg_audit = dpkg_audit(root)?;
+
+    Ok(CancelReport {
+        unit: current,
+        stopped,
+        dpkg_consistent: dpkg_audit.is_empty(),
+        dpkg_audit,
+    })
+}
+
+/// Run `dpkg --audit`, returns the reported problems.
+/// dpkg exits non-zero when problems are found, so don't use run_cmd here.
+fn dpkg_audit(root: &Path) -> Result<Vec<String>> {
+    let mut cmd = Command::new("dpkg");
+    if !sysroot::is_host(root) {
+        cmd.arg("--root").arg(root);
+    }
+    let out = cmd.arg("--audit").output()?;
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_packages() {
+        for p in [
+            "firefox",
+            "libc6",
+            "g++",
+            "libstdc++6",
+            "python3.12",
+            "0ad",
+            "foo:amd64",
+            "foo:loongarch64",
+            "foo=1.0",
+            "foo=1:2.0-1",
+            "foo=2.0~rc1+b1",
+            "foo:amd64=1.0-0ubuntu1",
+        ] {
+            assert_eq!(validate_package(p), Ok(()), "{p}");
+        }
+    }
+
+    #[test]
+    fn rejects_hostile_packages() {
+        for p in [
+            "",
+            "a",
+            "-y",
+            "--yes",
+            "--remove-config",
+            "-ofoo=bar",
+            "+foo",
+            ".foo",
+            "Foo",
+            "foo bar",
+            "foo\n--yes",
+            "foo\t",
+            "foo;rm -rf /",
+            "foo&&id",
+            "$(id)",
+            "`id`",
+            "foo|cat",
+            "../etc/passwd",
+            "/etc/passwd",
+            "./foo.deb",
+            "foo/bar",
+            "fоо", // cyrillic
+            "foo\0",
+            "foo:",
+            "foo:AMD64",
+            "foo:amd64:i386",
+            "foo:--yes",
+            "foo=",
+            "foo=abc",
+            "foo=-1",
+            "foo=1.0 --yes",
+            "foo=1.0;id",
+            "=1.0",
+            ":amd64",
+        ] {
+            assert!(validate_package(p).is_err(), "{p:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        assert!(validate_args(&["install", "--yes", "foo"]).is_ok());
+        assert!(validate_args(&["remove", "--remove-config", "foo"]).is_ok());
+        assert!(validate_args(&["remove", "--remove_config", "foo"]).is_err());
+        assert!(validate_args(&["install", "--remove-config", "foo"]).is_err());
+        assert!(validate_args(&["install", "-o", "foo"]).is_err());
+        assert!(validate_args(&["purge", "foo"]).is_err());
+        assert!(validate_args(&["mark", "hold", "foo"]).is_ok());
+        assert!(validate_args(&["mark", "manual", "foo"]).is_err());
+        assert!(validate_args(&["mark"]).is_err());
+        assert!(validate_args(&[]).is_err());
+    }
+
+    #[test]
+    fn parses_commands_with_sysroot() {
+        assert_eq!(
+            parse_command("/usr/bin/oma -- --sysroot /srv/rootfs upgrade --yes foo"),
+            (Some(OmaOperationKind::Upgrade), vec!["foo".to_string()])
+        );
+        assert_eq!(
+            parse_command("oma remove --remove-config foo bar"),
+            (
+                Some(OmaOperationKind::Remove),
+                vec!["foo".to_string(), "bar".to_string()]
+            )
+        );
+        assert_eq!(
+            parse_command("oma mark unhold foo"),
+            (Some(OmaOperationKind::Unhold), vec!["foo".to_string()])
+        );
+    }
+
+    #[test]
+    fn finds_lock_holders() {
+        let locks = "\
+1: POSIX  ADVISORY  WRITE 1234 103:1234:5678 0 EOF
+1: -> POSIX  ADVISORY  WRITE 999 103:1234:5678 0 EOF
+2: -> FLOCK  ADVISORY  WRITE 777 00:19:99 0 EOF
+3: FLOCK  ADVISORY  WRITE 4321 00:19:42 0 EOF
+4: OFDLCK ADVISORY  READ  -1 00:19:43 0 EOF
+";
+        // glibc makedev(0x103, 0x1234) and makedev(0, 0x19).
+        let nvme = 0x34 | (0x103 << 8) | (0x1200 << 12);
+        assert_eq!(find_lock_pid(locks, nvme, 5678), Some(1234));
+        assert_eq!(find_lock_pid(locks, 0x19, 42), Some(4321));
+        // only waited for, nobody holds it.
+        assert_eq!(find_lock_pid(locks, 0x19, 99), None);
+        // same inode on another device.
+        assert_eq!(find_lock_pid(locks, 0x18, 42), None);
+        assert_eq!(find_lock_pid(locks, 0x19, 43), None);
+        assert_eq!(find_lock_pid("", 0x19, 42), None);
+    }
+
+    #[test]
+    fn local_debs_stay_in_staging() {
+        let dir = Path::new("/home/user/.cache/aoska/debs");
+        assert!(is_local_deb("/home/user/.cache/aoska/debs/wechat.deb", dir));
+        assert!(!is_local_deb(
+            "/home/user/.cache/aoska/debs/../../../etc/foo.deb",
+            dir
+        ));
+        assert!(!is_local_deb("/tmp/foo.deb", dir));
+        assert!(!is_local_deb("/home/user/.cache/aoska/debs/foo.txt", dir));
+    }
+
+    fn local(y: i32, mo: u32, d: u32, h: u32, mi: u32, sec: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(y, mo, d, h, mi, sec)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parses_status() {
+        let out = "\
+unit=oma-task-20250610123456-a1b2c3
+state=succeeded
+started_at=2025-06-10T12:34:56+08:00
+finished_at=2025-06-10 12:40:01
+exit_code=0
+command=/usr/bin/oma --sysroot /var/lib/machines/os install --yes firefox vlc
+";
+        let unit = OmaUnit::parse("oma-task-20250610123456-a1b2c3", out);
+        assert_eq!(unit.unit, "oma-task-20250610123456-a1b2c3");
+        assert_eq!(unit.state, UnitState::Succeeded);
+        assert_eq!(
+            unit.started_at,
+            Some(Utc.with_ymd_and_hms(2025, 6, 10, 4, 34, 56).unwrap())
+        );
+        assert_eq!(unit.finished_at, Some(local(2025, 6, 10, 12, 40, 1)));
+        assert_eq!(unit.exit_code, Some(0));
+        assert_eq!(unit.operation, Some(OmaOperationKind::Install));
+        assert_eq!(unit.packages, ["firefox", "vlc"]);
+    }
+
+    #[test]
+    fn parses_sparse_status() {
+        // still running: no end, no exit code, the start comes from the unit name.
+        let out = "State = Active\ncommand=oma mark hold firefox\n";
+        let unit = OmaUnit::parse("oma-task-20250610123456-a1b2c3", out);
+        assert_eq!(unit.state, UnitState::Running);
+        assert_eq!(unit.started_at, Some(local(2025, 6, 10, 12, 34, 56)));
+        assert_eq!(unit.finished_at, None);
+        assert_eq!(unit.exit_code, None);
+        assert_eq!(unit.operation, Some(OmaOperationKind::Hold));
+        assert_eq!(unit.packages, ["firefox"]);
+
+        let unit = OmaUnit::parse("elsewhere", "");
+        assert_eq!(unit.unit, "elsewhere");
+        assert_eq!(unit.state, UnitState::Unknown);
+        assert_eq!(unit.started_at, None);
+        assert_eq!(unit.operation, None);
+    }
+
+    #[test]
+    fn parses_results() {
+        let ok = OmaUnitResult::parse("u", "unit=oma-task-1\nresult=success\nexit_code=0\n");
+        assert_eq!(ok.unit, "oma-task-1");
+        assert_eq!(ok.state, UnitState::Succeeded);
+        assert!(ok.success);
+
+        let failed = OmaUnitResult::parse("u", "result=failed\nexit_code=100\n");
+        assert_eq!(failed.unit, "u");
+        assert_eq!(failed.state, UnitState::Failed);
+        assert_eq!(failed.exit_code, Some(100));
+        assert!(!failed.success);
+
+        // a clean state with a bad exit code is no success either.
+        let odd = OmaUnitResult::parse("u", "state=inactive\nexit_code=1\n");
+        assert_eq!(odd.state, UnitState::Succeeded);
+        assert!(!odd.success);
+    }
+
+    #[test]
+    fn parses_states() {
+        for (s, state) in [
+            ("running", UnitState::Running),
+            ("activating", UnitState::Running),
+            ("SUCCESS", UnitState::Succeeded),
+            ("inactive", UnitState::Succeeded),
+            ("failed", UnitState::Failed),
+            ("canceled", UnitState::Cancelled),
+            ("cancelled", UnitState::Cancelled),
+            ("reloading", UnitState::Unknown),
+        ] {
+            assert_eq!(parse_state(Some(s)), state, "{s}");
+        }
+        assert_eq!(parse_state(None), UnitState::Unknown);
+    }
+
+    #[test]
+    fn reads_time_from_unit_names() {
+        assert_eq!(
+            time_from_unit_name("oma-task-20250610123456-a1b2c3"),
+            Some(local(2025, 6, 10, 12, 34, 56))
+        );
+        assert_eq!(
+            time_from_unit_name("oma-task-20250610123456-refresh"),
+            Some(local(2025, 6, 10, 12, 34, 56))
+        );
+        let name = new_unit_name("refresh");
+        assert!(time_from_unit_name(&name).is_some());
+        assert!(name.ends_with("-refresh"));
+        assert_ne!(name, new_unit_name("refresh"));
+        assert_eq!(time_from_unit_name("oma-task-2025-a1b2c3"), None);
+        assert_eq!(time_from_unit_name("oma-task-20251310123456-x"), None);
+        assert_eq!(time_from_unit_name("sshd.service"), None);
+    }
+
+    #[test]
+    fn parses_unit_lists() {
+        let out = "\
+UNIT                             STATE
+oma-task-20250610123456-a1b2c3   running
+oma-task-20250609080000-d4e5f6   failed
+oma-task-20250608080000-refresh
+";
+        let units = OmaUnit::parse_list(out);
+        let got: Vec<(&str, UnitState)> =
+            units.iter().map(|u| (u.unit.as_str(), u.state)).collect();
+        assert_eq!(
+            got,
+            [
+                ("oma-task-20250610123456-a1b2c3", UnitState::Running),
+                ("oma-task-20250609080000-d4e5f6", UnitState::Failed),
+                ("oma-task-20250608080000-refresh", UnitState::Unknown),
+            ]
+        );
+        assert_eq!(units[0].started_at, Some(local(2025, 6, 10, 12, 34, 56)));
+    }
+}
@@ -0,0 +1,361 @@
+use crate::common::apt_worker::APT_WORKER;
+use crate::common::history::record_task;
+use crate::common::oma::{
+    check_install, check_remove, check_security_upgrade, new_apt, topic_packages,
+    topics_in_upgrade, TumUpdateInfo,
+};
+use crate::common::omactl::{self, OmaOperationKind};
+use crate::common::polkit::Subject;
+use crate::common::safety::{check_acknowledged, check_protected, Confirmations};
+use anyhow::Result;
+use oma_pm::apt::{OmaApt, OmaOperation};
+use serde::Serialize;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Package operations as the GUI, the D-Bus service and the CLI run them:
+// previews, confirmation tokens, safety checks, omactl and the task history.
+
+/// How to run an oma task, see `omactl::run_oma`.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub wait: bool,
+    pub follow: bool,
+    pub unit: Option<String>,
+    pub assume_yes: bool,
+    pub lock_timeout: Option<Duration>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            wait: false,
+            follow: false,
+            unit: None,
+            assume_yes: true,
+            lock_timeout: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RemovePreview {
+    pub operation: OmaOperation,
+    /// Pass this to `remove` to confirm the operation.
+    pub token: String,
+}
+
+#[derive(Serialize)]
+pub struct TopicUpgradePreview {
+    pub topics: Vec<TumUpdateInfo>,
+    /// Packages of the topics to upgrade, the operation adds their dependencies.
+    pub packages: Vec<String>,
+    pub operation: OmaOperation,
+    /// Pass this to `topic_upgrade` to confirm the operation.
+    pub token: String,
+}
+
+#[derive(Serialize)]
+pub struct SecurityUpgradePreview {
+    /// Topics marked as security updates.
+    pub topics: Vec<TumUpdateInfo>,
+    /// Packages of those topics and from security pockets, the operation adds their dependencies.
+    pub packages: Vec<String>,
+    pub operation: OmaOperation,
+}
+
+/// The operation installing packages on the system at `root`, with dependencies,
+/// download size and disk delta. Nothing is executed.
+pub async fn preview_install(packages: &[String], root: &Path) -> Result<OmaOperation> {
+    check_packages(packages)?;
+    let pkgs = packages.to_vec();
+    with_apt(root, move |mut apt| async move {
+        check_install(&mut apt, &pkgs).await
+    })
+    .await
+}
+
+/// Start installing packages, returns the unit name.
+/// Fails if Essential or protected packages would be removed.
+pub async fn install(
+    packages: &[String],
+    root: &Path,
+    opts: &RunOptions,
+    subject: &Subject,
+) -> Result<String> {
+    check_protected(&preview_install(packages, root).await?, root)?;
+    let args = oma_args("install", &[], packages, opts);
+    start(
+        OmaOperationKind::Install,
+        args,
+        packages,
+        root,
+        opts,
+        subject,
+    )
+    .await
+}
+
+/// Preview removing packages, with the token `remove` needs to confirm it.
+/// Fails if Essential or protected packages would be removed.
+pub async fn preview_remove(
+    packages: &[String],
+    purge: bool,
+    root: &Path,
+    confirmations: &Mutex<Confirmations>,
+) -> Result<RemovePreview> {
+    check_packages(packages)?;
+    let pkgs = packages.to_vec();
+    let operation = with_apt(root, move |mut apt| async move {
+        check_remove(&mut apt, &pkgs, purge).await
+    })
+    .await?;
+    check_protected(&operation, root)?;
+    let token = confirmations.lock().unwrap().issue(
+        OmaOperationKind::Remove,
+        packages,
+        &[],
+        purge,
+        root,
+    )?;
+    Ok(RemovePreview { operation, token })
+}
+
+/// Start removing packages previewed by `preview_remove` with the same `purge`,
+/// returns the unit name.
+pub async fn remove(
+    packages: &[String],
+    token: &str,
+    purge: bool,
+    root: &Path,
+    confirmations: &Mutex<Confirmations>,
+    opts: &RunOptions,
+    subject: &Subject,
+) -> Result<String> {
+    check_packages(packages)?;
+    confirmations.lock().unwrap().redeem(
+        token,
+        OmaOperationKind::Remove,
+        packages,
+        &[],
+        purge,
+        root,
+    )?;
+    let flags: &[&str] = if purge { &["--remove-config"] } else { &[] };
+    let args = oma_args("remove", flags, packages, opts);
+    start(
+        OmaOperationKind::Remove,
+        args,
+        packages,
+        root,
+        opts,
+        subject,
+    )
+    .await
+}
+
+/// Start upgrading `packages`, or the whole system if empty. Returns the unit name.
+/// oma upgrades the whole system either way, so every topic with a caution
+/// must be in `acknowledged`. Fails if Essential or protected packages would be removed.
+pub async fn upgrade(
+    packages: &[String],
+    acknowledged: &[String],
+    root: &Path,
+    opts: &RunOptions,
+    subject: &Subject,
+) -> Result<String> {
+    omactl::validate_packages(packages)?;
+    let summary = APT_WORKER.summary(root).await?;
+    check_protected(&summary.operation, root)?;
+    check_acknowledged(topics_in_upgrade(&summary.tum, None), acknowledged)?;
+    let args = oma_args("upgrade", &[], packages, opts);
+    start(
+        OmaOperationKind::Upgrade,
+        args,
+        packages,
+        root,
+        opts,
+        subject,
+    )
+    .await
+}
+
+/// Preview upgrading only the packages of some topics, by `TumUpdateInfo.manifest_name`,
+/// with the token `topic_upgrade` needs to confirm it.
+pub async fn preview_topic_upgrade(
+    manifest_names: &[String],
+    root: &Path,
+    confirmations: &Mutex<Confirmations>,
+) -> Result<TopicUpgradePreview> {
+    let summary = APT_WORKER.summary(root).await?;
+    let (topics, packages) = topic_packages(&summary, manifest_names)?;
+    let pkgs = packages.clone();
+    let operation = with_apt(root, move |mut apt| async move {
+        check_install(&mut apt, &pkgs).await
+    })
+    .await?;
+    check_protected(&operation, root)?;
+    let token = confirmations.lock().unwrap().issue(
+        OmaOperationKind::Upgrade,
+        manifest_names,
+        &packages,
+        false,
+        root,
+    )?;
+    Ok(TopicUpgradePreview {
+        topics,
+        packages,
+        operation,
+        token,
+    })
+}
+
+/// Start upgrading the topics previewed by `preview_topic_upgrade`, returns the unit name.
+/// Topics with a caution must be in `acknowledged`.
+pub async fn topic_upgrade(
+    manifest_names: &[String],
+    token: &str,
+    acknowledged: &[String],
+    root: &Path,
+    confirmations: &Mutex<Confirmations>,
+    opts: &RunOptions,
+    subject: &Subject,
+) -> Result<String> {
+    let summary = APT_WORKER.summary(root).await?;
+    let (topics, packages) = topic_packages(&summary, manifest_names)?;
+    // before redeeming, so the token survives a missing acknowledgement.
+    check_acknowledged(&topics, acknowledged)?;
+    // the topics may resolve to other packages since the preview, that voids the token.
+    confirmations.lock().unwrap().redeem(
+        token,
+        OmaOperationKind::Upgrade,
+        manifest_names,
+        &packages,
+        false,
+        root,
+    )?;
+    upgrade_only(&packages, root, opts, subject).await
+}
+
+/// Preview the upgrade `security_upgrade` runs: security topics, their packages
+/// plus those from security pockets, and the operation.
+pub async fn preview_security_upgrade(root: &Path) -> Result<SecurityUpgradePreview> {
+    let summary = APT_WORKER.summary(root).await?;
+    let (topics, packages, operation) = with_apt(root, move |mut apt| async move {
+        check_security_upgrade(&mut apt, &summary).await
+    })
+    .await?;
+    check_protected(&operation, root)?;
+    Ok(SecurityUpgradePreview {
+        topics,
+        packages,
+        operation,
+    })
+}
+
+/// Start upgrading only the packages `preview_security_upgrade` shows, returns the unit name.
+/// Topics with a caution must be in `acknowledged`.
+pub async fn security_upgrade(
+    acknowledged: &[String],
+    root: &Path,
+    opts: &RunOptions,
+    subject: &Subject,
+) -> Result<String> {
+    let summary = APT_WORKER.summary(root).await?;
+    let packages = preview_security_upgrade(root).await?.packages;
+    // oma would take no packages as the whole upgrade.
+    if packages.is_empty() {
+        anyhow::bail!("no security updates");
+    }
+    check_acknowledged(
+        topics_in_upgrade(&summary.tum, Some(&packages)),
+        acknowledged,
+    )?;
+    upgrade_only(&packages, root, opts, subject).await
+}
+
+// install, as previewed: `oma upgrade` would upgrade everything else as well.
+async fn upgrade_only(
+    packages: &[String],
+    root: &Path,
+    opts: &RunOptions,
+    subject: &Subject,
+) -> Result<String> {
+    let args = oma_args("install", &[], packages, opts);
+    start(
+        OmaOperationKind::Upgrade,
+        args,
+        packages,
+        root,
+        opts,
+        subject,
+    )
+    .await
+}
+
+fn check_packages(packages: &[String]) -> Result<()> {
+    if packages.is_empty() {
+        anyhow::bail!("packages is empty");
+    }
+    Ok(omactl::validate_packages(packages)?)
+}
+
+// oma <subcommand> [--yes] [flags] <packages>
+fn oma_args(
+    subcommand: &str,
+    flags: &[&str],
+    packages: &[String],
+    opts: &RunOptions,
+) -> Vec<String> {
+    let mut args = vec![subcommand.to_string()];
+    if opts.assume_yes {
+        args.push("--yes".to_string());
+    }
+    args.extend(flags.iter().map(|f| f.to_string()));
+    args.extend(packages.iter().cloned());
+    args
+}
+
+// Run the task via omactl and record it in the history.
+async fn start(
+    kind: OmaOperationKind,
+    args: Vec<String>,
+    packages: &[String],
+    root: &Path,
+    opts: &RunOptions,
+    subject: &Subject,
+) -> Result<String> {
+    let (root, opts, by) = (root.to_path_buf(), opts.clone(), subject.clone());
+    let unit = tokio::task::spawn_blocking(move || {
+        let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        omactl::run_oma(
+            &args,
+            opts.wait,
+            opts.follow,
+            opts.unit.as_deref(),
+            opts.lock_timeout,
+            &root,
+            &by,
+        )
+    })
+    .await??;
+    record_task(kind, packages, &unit, subject);
+    Ok(unit)
+}
+
+// OmaApt is not Send, build and use it on a blocking thread.
+async fn with_apt<T, F, Fut>(root: &Path, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(OmaApt) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T>>,
+{
+    let root = root.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let apt = new_apt(&root, true)?;
+        tokio::runtime::Handle::current().block_on(f(apt))
+    })
+    .await?
+}
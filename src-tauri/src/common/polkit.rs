@@ -0,0 +1,165 @@
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::process::Command;
+use strum_macros::{Display, EnumString};
+use thiserror::Error;
+
+/// Polkit actions, see polkit/com.aosc.aoska.policy.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, EnumString, Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum PolkitAction {
+    Install,
+    Remove,
+    Upgrade,
+    Refresh,
+    Hold,
+}
+
+impl PolkitAction {
+    pub fn id(&self) -> String {
+        format!("com.aosc.aoska.{self}")
+    }
+
+    /// The action guarding an oma subcommand.
+    pub fn for_oma(subcommand: &str) -> Option<Self> {
+        match subcommand {
+            "install" => Some(Self::Install),
+            "remove" => Some(Self::Remove),
+            "upgrade" => Some(Self::Upgrade),
+            "refresh" => Some(Self::Refresh),
+            "mark" => Some(Self::Hold),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("not authorized: {0}")]
+    NotAuthorized(PolkitAction),
+
+    #[error("authentication dismissed: {0}")]
+    Dismissed(PolkitAction),
+
+    #[error("polkit error: {0}")]
+    Polkit(String),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// The process polkit checks, identified by `pid,start-time,uid`
+/// since the bare pid form of `pkcheck --process` is racy against pid reuse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subject {
+    pub pid: u32,
+    pub start_time: u64,
+    pub uid: u32,
+    /// Whether the authentication agent may prompt the subject's user.
+    pub interactive: bool,
+}
+
+impl Subject {
+    /// This process.
+    pub fn current() -> io::Result<Self> {
+        Self::process(std::process::id())
+    }
+
+    /// Another process, e.g. the sender of a D-Bus call.
+    pub fn process(pid: u32) -> io::Result<Self> {
+        let stat = fs::read_to_string(format!("/proc/{pid}/stat"))?;
+        let status = fs::read_to_string(format!("/proc/{pid}/status"))?;
+        let (start_time, uid) = parse_proc(&stat, &status).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("bad /proc/{pid} entry"))
+        })?;
+        Ok(Self {
+            pid,
+            start_time,
+            uid,
+            interactive: true,
+        })
+    }
+
+    /// Fail rather than prompt when authentication is needed, e.g. for background tasks.
+    pub fn non_interactive(self) -> Self {
+        Self {
+            interactive: false,
+            ..self
+        }
+    }
+}
+
+/// Ask polkit whether `subject` may perform `action`,
+/// letting the authentication agent prompt the user if needed, unless the subject is non-interactive.
+///
+/// This runs unprivileged, in the client or the session service, so it only
+/// keeps them honest: omactl does not rely on it and must enforce its own authorization.
+pub fn authorize(action: PolkitAction, subject: &Subject) -> Result<(), AuthError> {
+    let mut cmd = Command::new("pkcheck");
+    cmd.arg("--action-id")
+        .arg(action.id())
+        .arg("--process")
+        .arg(format!(
+            "{},{},{}",
+            subject.pid, subject.start_time, subject.uid
+        ));
+    if subject.interactive {
+        cmd.arg("--allow-user-interaction");
+    }
+    let out = cmd.output()?;
+
+    // see pkcheck(1) for the exit codes.
+    match out.status.code() {
+        Some(0) => Ok(()),
+        Some(1) | Some(2) => Err(AuthError::NotAuthorized(action)),
+        Some(3) => Err(AuthError::Dismissed(action)),
+        _ => Err(AuthError::Polkit(
+            String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        )),
+    }
+}
+
+/// Start time (field 22 of stat) and real uid (from status) of a process.
+fn parse_proc(stat: &str, status: &str) -> Option<(u64, u32)> {
+    // comm may contain spaces and parens, so count fields after the last ')'.
+    // state is field 3, so start time is the 20th after it.
+    let start_time = stat
+        .get(stat.rfind(')')? + 1..)?
+        .split_whitespace()
+        .nth(19)?
+        .parse()
+        .ok()?;
+    let uid = status
+        .lines()
+        .find_map(|l| l.strip_prefix("Uid:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    Some((start_time, uid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_proc() {
+        let stat = "4242 (a (b) c) S 1 4242 4242 0 -1 4194560 1 0 0 0 0 0 0 0 20 0 1 0 123456 2 3";
+        let status = "Name:\ta\nUid:\t1000\t1000\t1000\t1000\nGid:\t100\n";
+        assert_eq!(parse_proc(stat, status), Some((123456, 1000)));
+        assert_eq!(parse_proc("4242 (a) S 1", status), None);
+        assert_eq!(parse_proc(stat, "Name:\ta\n"), None);
+    }
+
+    #[test]
+    fn describes_self() {
+        let subject = Subject::current().unwrap();
+        assert_eq!(subject.pid, std::process::id());
+        assert!(subject.start_time > 0);
+        assert!(subject.interactive);
+        assert!(!subject.non_interactive().interactive);
+    }
+}
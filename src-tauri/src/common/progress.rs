@@ -0,0 +1,248 @@
+use ahash::HashSet;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use strum_macros::{Display, EnumString};
+
+// dpkg status lines, e.g. "Unpacking foo:amd64 (1.0-1) over (0.9-1) ..."
+static DPKG_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(Unpacking|Setting up|Removing|Purging configuration files for|Processing triggers for) ([^\s:]+)(?::\S+)? ")
+        .unwrap()
+});
+// "3/17", "[3/17]", "(3/17)"
+static COUNT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d+)\s*/\s*(\d+)\b").unwrap());
+static PERCENT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d+(?:\.\d+)?)\s*%").unwrap());
+// "1.5 MiB/20.0 MiB", "512 KiB / 3 MiB"
+static BYTES_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"([\d.]+)\s*([KMGT]?i?B)\s*/\s*([\d.]+)\s*([KMGT]?i?B)").unwrap());
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, EnumString, Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum ProgressPhase {
+    Downloading,
+    Unpacking,
+    Configuring,
+    Removing,
+    Triggers,
+}
+
+/// Progress of an oma task, parsed from its log.
+#[derive(Debug, Clone, Serialize)]
+pub struct OmaProgress {
+    pub phase: ProgressPhase,
+    pub package: Option<String>,
+    /// Packages done / total in this phase.
+    pub current: Option<u64>,
+    pub total: Option<u64>,
+    pub bytes_done: Option<u64>,
+    pub bytes_total: Option<u64>,
+    pub percent: Option<f32>,
+}
+
+/// Turns oma log lines into progress events.
+/// Keeps state across lines since dpkg doesn't print counters by itself.
+#[derive(Debug, Default)]
+pub struct ProgressTracker {
+    phase: Option<ProgressPhase>,
+    /// Packages already seen in the current dpkg phase.
+    seen: HashSet<String>,
+    /// Package count announced by oma, e.g. by the download counter.
+    total: Option<u64>,
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a log line, returns a progress event if the line carries one.
+    pub fn feed(&mut self, line: &str) -> Option<OmaProgress> {
+        let line = line.trim();
+        if let Some(cap) = DPKG_RE.captures(line) {
+            let phase = match &cap[1] {
+                "Unpacking" => ProgressPhase::Unpacking,
+                "Setting up" => ProgressPhase::Configuring,
+                "Removing" | "Purging configuration files for" => ProgressPhase::Removing,
+                _ => ProgressPhase::Triggers,
+            };
+            return Some(self.dpkg_progress(phase, &cap[2]));
+        }
+
+        if !line.to_lowercase().contains("download") {
+            return None;
+        }
+        let (current, total) = match COUNT_RE.captures(line) {
+            Some(cap) => (cap[1].parse().ok(), cap[2].parse().ok()),
+            None => (None, None),
+        };
+        self.total = total.or(self.total);
+        let (bytes_done, bytes_total) = match BYTES_RE.captures(line) {
+            Some(cap) => (parse_size(&cap[1], &cap[2]), parse_size(&cap[3], &cap[4])),
+            None => (None, None),
+        };
+        let percent = PERCENT_RE
+            .captures(line)
+            .and_then(|cap| cap[1].parse().ok())
+            .or_else(|| ratio(bytes_done, bytes_total))
+            .or_else(|| ratio(current, total));
+        self.phase = Some(ProgressPhase::Downloading);
+
+        Some(OmaProgress {
+            phase: ProgressPhase::Downloading,
+            package: None,
+            current,
+            total: self.total,
+            bytes_done,
+            bytes_total,
+            percent,
+        })
+    }
+
+    fn dpkg_progress(&mut self, phase: ProgressPhase, package: &str) -> OmaProgress {
+        if self.phase != Some(phase) {
+            self.phase = Some(phase);
+            self.seen.clear();
+        }
+        self.seen.insert(package.to_string());
+        let current = self.seen.len() as u64;
+        // triggers are not per package, the download count means nothing there.
+        let total = match phase {
+            ProgressPhase::Triggers => None,
+            _ => self.total.filter(|t| *t >= current),
+        };
+
+        OmaProgress {
+            phase,
+            package: Some(package.to_string()),
+            current: Some(current),
+            total,
+            bytes_done: None,
+            bytes_total: None,
+            percent: ratio(Some(current), total),
+        }
+    }
+}
+
+fn ratio(done: Option<u64>, total: Option<u64>) -> Option<f32> {
+    match (done, total) {
+        (Some(d), Some(t)) if t > 0 => Some(d as f32 * 100.0 / t as f32),
+        _ => None,
+    }
+}
+
+fn parse_size(value: &str, unit: &str) -> Option<u64> {
+    let value: f64 = value.parse().ok()?;
+    let scale = match unit {
+        "B" => 1.0,
+        "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((value * scale) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dpkg_lines() {
+        let mut tracker = ProgressTracker::new();
+        let p = tracker
+            .feed("Unpacking libfoo:amd64 (1.0-1) over (0.9-1) ...")
+            .unwrap();
+        assert_eq!(p.phase, ProgressPhase::Unpacking);
+        assert_eq!(p.package.as_deref(), Some("libfoo"));
+        assert_eq!((p.current, p.total, p.percent), (Some(1), None, None));
+
+        let p = tracker
+            .feed("Purging configuration files for bar (2.0) ...")
+            .unwrap();
+        assert_eq!(p.phase, ProgressPhase::Removing);
+        assert_eq!(p.package.as_deref(), Some("bar"));
+
+        let p = tracker.feed("Processing triggers for man-db (2.12) ...");
+        assert_eq!(p.unwrap().phase, ProgressPhase::Triggers);
+
+        assert!(tracker.feed("Reading package lists... Done").is_none());
+        assert!(tracker.feed("Unpacking").is_none());
+    }
+
+    #[test]
+    fn parses_download_counters() {
+        let mut tracker = ProgressTracker::new();
+        let p = tracker.feed("Downloading [3/4] foo_1.0_amd64.deb").unwrap();
+        assert_eq!(p.phase, ProgressPhase::Downloading);
+        assert_eq!((p.current, p.total), (Some(3), Some(4)));
+        assert_eq!(p.percent, Some(75.0));
+
+        // the total is remembered for lines without a counter.
+        let p = tracker.feed("Downloading bar_2.0_amd64.deb").unwrap();
+        assert_eq!((p.current, p.total, p.percent), (None, Some(4), None));
+    }
+
+    #[test]
+    fn parses_bytes_and_percent() {
+        let mut tracker = ProgressTracker::new();
+        let p = tracker.feed("Downloading 1.5 MiB / 3 MiB").unwrap();
+        assert_eq!(p.bytes_done, Some(1024 * 1024 * 3 / 2));
+        assert_eq!(p.bytes_total, Some(3 * 1024 * 1024));
+        assert_eq!(p.percent, Some(50.0));
+
+        let p = tracker.feed("Downloading 512KB/2MB").unwrap();
+        assert_eq!(
+            (p.bytes_done, p.bytes_total),
+            (Some(512_000), Some(2_000_000))
+        );
+
+        // an explicit percentage wins over the computed one.
+        let p = tracker.feed("Download 1 MiB/4 MiB 30.5%").unwrap();
+        assert_eq!(p.percent, Some(30.5));
+
+        assert_eq!(parse_size("1", "PB"), None);
+        assert_eq!(parse_size("x", "B"), None);
+    }
+
+    #[test]
+    fn resets_counts_between_phases() {
+        let mut tracker = ProgressTracker::new();
+        tracker.feed("Downloading (2/2) b.deb");
+        assert_eq!(
+            tracker.feed("Unpacking a (1) ...").unwrap().current,
+            Some(1)
+        );
+        let p = tracker.feed("Unpacking b (1) ...").unwrap();
+        assert_eq!(
+            (p.current, p.total, p.percent),
+            (Some(2), Some(2), Some(100.0))
+        );
+        // the same package again is not counted twice.
+        assert_eq!(
+            tracker.feed("Unpacking b (1) ...").unwrap().current,
+            Some(2)
+        );
+
+        let p = tracker.feed("Setting up a (1) ...").unwrap();
+        assert_eq!(p.phase, ProgressPhase::Configuring);
+        assert_eq!((p.current, p.total), (Some(1), Some(2)));
+
+        // triggers have no meaningful total.
+        let p = tracker
+            .feed("Processing triggers for libc-bin (2.40) ...")
+            .unwrap();
+        assert_eq!((p.current, p.total), (Some(1), None));
+
+        // more packages than announced, the total is not trusted anymore.
+        tracker.feed("Removing x (1) ...");
+        tracker.feed("Removing y (1) ...");
+        let p = tracker.feed("Removing z (1) ...").unwrap();
+        assert_eq!((p.current, p.total), (Some(3), None));
+    }
+}
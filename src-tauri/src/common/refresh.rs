@@ -0,0 +1,236 @@
+use crate::common::apt_worker::APT_WORKER;
+use crate::common::config::LAST_REFRESH_PATH;
+use crate::common::polkit::Subject;
+use crate::common::{journal, omactl, sysroot};
+use ahash::HashMap;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+static MIRROR_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://([^/\s]+)").unwrap());
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MirrorState {
+    Fetching,
+    Done,
+    Failed,
+}
+
+/// A log line of the refresh concerning one mirror.
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorProgress {
+    pub mirror: String,
+    pub state: MirrorState,
+    pub line: String,
+}
+
+/// Follows the refresh log, oma mentions a mirror's URL in each line about it.
+#[derive(Debug, Default)]
+pub struct RefreshTracker {
+    mirrors: HashMap<String, MirrorState>,
+    errors: Vec<String>,
+}
+
+impl RefreshTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a log line with its syslog priority, returns the progress of a mirror if it names one.
+    pub fn feed(&mut self, line: &str, priority: Option<u8>) -> Option<MirrorProgress> {
+        let line = line.trim();
+        let lower = line.to_lowercase();
+        let failed =
+            priority.is_some_and(|p| p <= 3) || lower.contains("error") || lower.contains("failed");
+        if failed {
+            self.errors.push(line.to_string());
+        }
+
+        let mirror = MIRROR_RE.captures(line)?[1].to_string();
+        let state = if failed {
+            MirrorState::Failed
+        } else {
+            // a mirror which failed once stays failed.
+            match self.mirrors.get(&mirror) {
+                Some(MirrorState::Failed) => MirrorState::Failed,
+                _ => MirrorState::Fetching,
+            }
+        };
+        self.mirrors.insert(mirror.clone(), state);
+        Some(MirrorProgress {
+            mirror,
+            state,
+            line: line.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RefreshReport {
+    pub unit: String,
+    pub succeeded: bool,
+    pub mirrors: HashMap<String, MirrorState>,
+    pub errors: Vec<String>,
+    /// Set when the refresh succeeded.
+    pub refreshed_at: Option<DateTime<Utc>>,
+}
+
+/// Refresh the package database of the system at `root` via omactl and wait for it
+/// to finish on behalf of `subject`, calling `on_progress` for each line about a mirror.
+pub fn run_refresh<F>(
+    root: &Path,
+    lock_timeout: Option<Duration>,
+    subject: &Subject,
+    mut on_progress: F,
+) -> RefreshReport
+where
+    F: FnMut(&str, MirrorProgress) + Send + 'static,
+{
+    // name the unit ourselves so its log can be followed from the start.
+    let unit = omactl::new_unit_name("refresh");
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let follower = {
+        let unit = unit.clone();
+        thread::spawn(move || {
+            let mut tracker = RefreshTracker::new();
+            let res = journal::follow_unit(&unit, None, &stop_rx, |entry| {
+                if let Some(progress) = tracker.feed(&entry.line, entry.priority) {
+                    on_progress(&unit, progress);
+                }
+            });
+            if let Err(e) = res {
+                tracker.errors.push(format!("failed to read journal: {e}"));
+            }
+            tracker
+        })
+    };
+
+    let result = omactl::run_oma(
+        &["refresh"],
+        true,
+        false,
+        Some(&unit),
+        lock_timeout,
+        root,
+        subject,
+    );
+    thread::sleep(journal::FLUSH_DELAY);
+    let _ = stop_tx.send(());
+    let mut tracker = follower.join().unwrap_or_default();
+
+    let refreshed_at = match result {
+        Ok(_) => {
+            let now = Utc::now();
+            // failing to remember the time must not fail the refresh.
+            // only the host's counts, the scheduler checks against it.
+            if sysroot::is_host(root) {
+                let _ = record_refresh(now);
+            }
+            APT_WORKER.invalidate();
+            tracker
+                .mirrors
+                .values_mut()
+                .filter(|s| **s == MirrorState::Fetching)
+                .for_each(|s| *s = MirrorState::Done);
+            Some(now)
+        }
+        Err(e) => {
+            tracker.errors.push(e.to_string());
+            None
+        }
+    };
+    RefreshReport {
+        unit,
+        succeeded: refreshed_at.is_some(),
+        mirrors: tracker.mirrors,
+        errors: tracker.errors,
+        refreshed_at,
+    }
+}
+
+fn last_refresh_path() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join(LAST_REFRESH_PATH))
+}
+
+/// Time of the last successful refresh from aoska, if any.
+pub fn last_refresh() -> Option<DateTime<Utc>> {
+    let s = fs::read_to_string(last_refresh_path()?).ok()?;
+    DateTime::parse_from_rfc3339(s.trim())
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+fn record_refresh(at: DateTime<Utc>) -> io::Result<()> {
+    let path = last_refresh_path().ok_or(io::ErrorKind::NotFound)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, at.to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_mirrors() {
+        let mut tracker = RefreshTracker::new();
+        assert!(tracker
+            .feed("Refreshing local database ...", Some(6))
+            .is_none());
+
+        let p = tracker
+            .feed(
+                "  Downloading https://repo.aosc.io/debs/dists/stable/InRelease",
+                Some(6),
+            )
+            .unwrap();
+        assert_eq!(p.mirror, "repo.aosc.io");
+        assert_eq!(p.state, MirrorState::Fetching);
+        assert_eq!(
+            p.line,
+            "Downloading https://repo.aosc.io/debs/dists/stable/InRelease"
+        );
+
+        let p = tracker
+            .feed(
+                "Failed to download http://mirrors.tuna.tsinghua.edu.cn/anthon/debs: 404",
+                Some(6),
+            )
+            .unwrap();
+        assert_eq!(p.mirror, "mirrors.tuna.tsinghua.edu.cn");
+        assert_eq!(p.state, MirrorState::Failed);
+        // a mirror which failed once stays failed.
+        let p = tracker
+            .feed(
+                "Downloading http://mirrors.tuna.tsinghua.edu.cn/anthon/debs/Packages",
+                Some(6),
+            )
+            .unwrap();
+        assert_eq!(p.state, MirrorState::Failed);
+
+        // errors without a mirror are kept too, the priority alone tells.
+        assert!(tracker.feed("No space left on device", Some(3)).is_none());
+
+        assert_eq!(tracker.mirrors["repo.aosc.io"], MirrorState::Fetching);
+        assert_eq!(
+            tracker.mirrors["mirrors.tuna.tsinghua.edu.cn"],
+            MirrorState::Failed
+        );
+        assert_eq!(
+            tracker.errors,
+            [
+                "Failed to download http://mirrors.tuna.tsinghua.edu.cn/anthon/debs: 404",
+                "No space left on device",
+            ]
+        );
+    }
+}
@@ -1,10 +1,14 @@
-use crate::common::config::LOCAL_REPO_PATH;
+use crate::common::config::{DEB_STAGING_PATH, LOCAL_REPO_PATH};
+use crate::common::deb::{read_control, DebError};
+use crate::common::history::record_task;
+use crate::common::omactl::{self, OmaOperationKind};
+use crate::common::polkit::Subject;
 use crate::common::utils::run_cmd;
 use ahash::{HashMap, HashMapExt};
-use regex::Regex;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 use thiserror::Error;
 use walkdir::WalkDir;
 
@@ -20,6 +24,12 @@ pub enum RepoError {
     #[error("package not found: {0}")]
     NotFound(String),
 
+    #[error("deb error: {0}")]
+    Deb(#[from] DebError),
+
+    #[error("bad Package field in deb: {0}")]
+    InvalidPackage(#[from] omactl::InvalidPackageError),
+
     // we could have better error handling.
     #[error("command error: {0}")]
     Other(#[from] anyhow::Error),
@@ -36,17 +46,17 @@ impl RepoManager {
     }
 
     /// add a package to local repo.
-    /// copy from temp folder.
-    pub fn add_package(
-        &mut self,
-        package_name: String,
-        package_path: PathBuf,
-    ) -> Result<(), RepoError> {
-        if Self::is_deb(&package_path) {
+    /// copy from temp folder, returns the path of the deb in local repo.
+    pub fn add_package(&mut self, package_path: PathBuf) -> Result<PathBuf, RepoError> {
+        if !Self::is_deb(&package_path) {
             return Err(
                 std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a deb file").into(),
             );
         }
+        // take the name from control file instead of trusting the file name.
+        let package_name = read_control(&package_path)?.package;
+        // it ends up in a path, e.g. `Package: ../../etc/foo` must not escape the repo.
+        omactl::validate_package(&package_name)?;
         let pkgfile_name = format!("{package_name}.deb");
         let dst = Path::new(LOCAL_REPO_PATH).join(&pkgfile_name);
         // remove duplicated file first.
@@ -54,9 +64,9 @@ impl RepoManager {
         fs::remove_file(&dst).ok();
         fs::copy(package_path, &dst)?;
 
-        self.packages.insert(pkgfile_name.clone(), dst.clone());
+        self.packages.insert(package_name, dst.clone());
         Self::generate_packages_index()?;
-        Ok(())
+        Ok(dst)
     }
 
     pub fn remove_package(&mut self, package_name: &str) -> Result<(), RepoError> {
@@ -85,13 +95,10 @@ impl RepoManager {
             } // ignore non-file.
             let path = entry.into_path();
             if Self::is_deb(&path) {
-                let filename = path
-                    .file_name()
-                    .and_then(|os| os.to_str())
-                    .unwrap_or_default()
-                    .to_string();
-                let pkg_name = Self::extract_name(&filename).unwrap();
-                packages_map.entry(pkg_name).insert_entry(path);
+                // skip broken debs, dpkg-scanpackages will complain about them anyway.
+                if let Ok(control) = read_control(&path) {
+                    packages_map.entry(control.package).insert_entry(path);
+                }
             }
         }
         Self::generate_packages_index()?;
@@ -112,11 +119,57 @@ impl RepoManager {
             .and_then(|s| s.to_str())
             .is_some_and(|e| e.eq_ignore_ascii_case("deb"))
     }
+}
+
+/// Where local debs are staged for oma to install them, see `stage_deb`.
+pub fn staging_dir() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join(DEB_STAGING_PATH))
+}
+
+/// Copy a local deb to the staging directory, named after its package,
+/// so oma installs the very file which was checked. Returns the staged path.
+/// The local repo belongs to root, installing from it would need a privileged copy.
+pub fn stage_deb(path: &Path) -> Result<PathBuf, RepoError> {
+    if !RepoManager::is_deb(path) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a deb file").into());
+    }
+    // take the name from control file instead of trusting the file name.
+    let package_name = read_control(path)?.package;
+    omactl::validate_package(&package_name)?;
+    let dir = staging_dir().ok_or_else(|| anyhow::anyhow!("no cache directory"))?;
+    fs::create_dir_all(&dir)?;
+    let dst = dir.join(format!("{package_name}.deb"));
+    fs::copy(path, &dst)?;
+    Ok(dst)
+}
+
+/// Stage a local deb file and install it via omactl into the system at `root`
+/// on behalf of `subject`. Returns the unit name.
+#[allow(clippy::too_many_arguments)]
+pub fn import_and_install(
+    path: PathBuf,
+    root: &Path,
+    assume_yes: bool,
+    wait: bool,
+    follow: bool,
+    unit: Option<&str>,
+    lock_timeout: Option<Duration>,
+    subject: &Subject,
+) -> anyhow::Result<String> {
+    let deb_path = stage_deb(&path)?;
+    let deb_path = deb_path.to_string_lossy();
 
-    /// deb name definition: <package_name>.deb
-    fn extract_name(filename: &str) -> Option<String> {
-        let re = Regex::new(r"^([A-Za-z0-9\-\+\.])+(?:\.deb)?$").ok()?;
-        re.captures(filename)
-            .and_then(|cap| cap.get(1).map(|m| m.as_str().to_string()))
+    let mut args: Vec<&str> = vec!["install"];
+    if assume_yes {
+        args.push("--yes");
     }
+    args.push(&deb_path);
+    let started = omactl::run_oma(&args, wait, follow, unit, lock_timeout, root, subject)?;
+    record_task(
+        OmaOperationKind::Install,
+        &[deb_path.to_string()],
+        &started,
+        subject,
+    );
+    Ok(started)
 }
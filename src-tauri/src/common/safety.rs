@@ -0,0 +1,274 @@
+use crate::common::oma::TumUpdateInfo;
+use crate::common::omactl::OmaOperationKind;
+use crate::common::sysroot;
+use ahash::{HashMap, HashSet};
+use oma_pm::apt::OmaOperation;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// A confirmation token is only valid for this long after the preview.
+const TOKEN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Packages which must never be removed from aoska, on top of those marked Essential.
+const PROTECTED_PACKAGES: &[&str] = &["aosc-aaa", "oma", "dpkg"];
+
+pub const DPKG_STATUS_PATH: &str = "/var/lib/dpkg/status";
+
+#[derive(Error, Debug)]
+pub enum SafetyError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("protected packages would be removed: {}", .0.join(", "))]
+    Protected(Vec<String>),
+
+    #[error("invalid or expired confirmation token, preview the operation again")]
+    InvalidToken,
+
+    #[error("cautions of these topics must be acknowledged first: {}", .0.join(", "))]
+    Unacknowledged(Vec<String>),
+}
+
+struct Pending {
+    kind: OmaOperationKind,
+    packages: Vec<String>,
+    resolved: Vec<String>,
+    purge: bool,
+    root: PathBuf,
+    issued_at: Instant,
+}
+
+/// Confirmation tokens handed out by previews.
+/// A destructive operation only runs with the token of a preview of the same operation.
+#[derive(Default)]
+pub struct Confirmations {
+    pending: HashMap<String, Pending>,
+}
+
+impl Confirmations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `resolved` is what `packages` stood for at preview time, like the packages
+    /// of topics, so the token is void once it changes. Empty if `packages` are packages.
+    /// The token is only good for the system at `root`.
+    pub fn issue(
+        &mut self,
+        kind: OmaOperationKind,
+        packages: &[String],
+        resolved: &[String],
+        purge: bool,
+        root: &Path,
+    ) -> Result<String, SafetyError> {
+        self.pending
+            .retain(|_, p| p.issued_at.elapsed() < TOKEN_TTL);
+        let token = random_token()?;
+        self.pending.insert(
+            token.clone(),
+            Pending {
+                kind,
+                packages: sorted(packages),
+                resolved: sorted(resolved),
+                purge,
+                root: root.to_path_buf(),
+                issued_at: Instant::now(),
+            },
+        );
+        Ok(token)
+    }
+
+    /// Check a token against the operation about to run. Tokens are single use.
+    pub fn redeem(
+        &mut self,
+        token: &str,
+        kind: OmaOperationKind,
+        packages: &[String],
+        resolved: &[String],
+        purge: bool,
+        root: &Path,
+    ) -> Result<(), SafetyError> {
+        let pending = self
+            .pending
+            .remove(token)
+            .ok_or(SafetyError::InvalidToken)?;
+        if pending.issued_at.elapsed() >= TOKEN_TTL
+            || pending.kind != kind
+            || pending.packages != sorted(packages)
+            || pending.resolved != sorted(resolved)
+            || pending.purge != purge
+            || pending.root != root
+        {
+            return Err(SafetyError::InvalidToken);
+        }
+        Ok(())
+    }
+}
+
+/// Refuse operations removing Essential or protected packages of the system at `root`.
+pub fn check_protected(op: &OmaOperation, root: &Path) -> Result<(), SafetyError> {
+    let essential = essential_packages(root)?;
+    let hits: Vec<String> = op
+        .remove
+        .iter()
+        .map(|r| r.name().to_string())
+        .filter(|n| PROTECTED_PACKAGES.contains(&n.as_str()) || essential.contains(n))
+        .collect();
+    if hits.is_empty() {
+        Ok(())
+    } else {
+        Err(SafetyError::Protected(hits))
+    }
+}
+
+/// Refuse upgrades containing topics with a caution which was not acknowledged,
+/// `acknowledged` being manifest names.
+pub fn check_acknowledged<'a>(
+    topics: impl IntoIterator<Item = &'a TumUpdateInfo>,
+    acknowledged: &[String],
+) -> Result<(), SafetyError> {
+    let missing: Vec<String> = topics
+        .into_iter()
+        .filter(|t| t.requires_ack && !acknowledged.contains(&t.manifest_name))
+        .map(|t| t.manifest_name.clone())
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(SafetyError::Unacknowledged(missing))
+    }
+}
+
+/// Installed packages marked `Essential: yes` in the dpkg status file.
+fn essential_packages(root: &Path) -> Result<HashSet<String>, SafetyError> {
+    let status = fs::read_to_string(sysroot::join(root, DPKG_STATUS_PATH))?;
+    Ok(status
+        .split("\n\n")
+        .filter(|p| p.lines().any(|l| l.eq_ignore_ascii_case("Essential: yes")))
+        .filter_map(|p| p.lines().find_map(|l| l.strip_prefix("Package: ")))
+        .map(|n| n.trim().to_string())
+        .collect())
+}
+
+fn random_token() -> Result<String, SafetyError> {
+    let mut buf = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut buf)?;
+    Ok(buf.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+fn sorted(packages: &[String]) -> Vec<String> {
+    let mut v = packages.to_vec();
+    v.sort();
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPGRADE: OmaOperationKind = OmaOperationKind::Upgrade;
+    const REMOVE: OmaOperationKind = OmaOperationKind::Remove;
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn redeems_tokens_once() {
+        let mut confirmations = Confirmations::new();
+        let root = Path::new("/");
+        let topics = strings(&["kde"]);
+        let packages = strings(&["plasma", "kwin"]);
+        let token = confirmations
+            .issue(UPGRADE, &topics, &packages, false, root)
+            .unwrap();
+        // order does not matter.
+        let reordered = strings(&["kwin", "plasma"]);
+        confirmations
+            .redeem(&token, UPGRADE, &topics, &reordered, false, root)
+            .unwrap();
+        assert!(matches!(
+            confirmations.redeem(&token, UPGRADE, &topics, &packages, false, root),
+            Err(SafetyError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn rejects_changed_operations() {
+        let mut confirmations = Confirmations::new();
+        let root = Path::new("/");
+        let topics = strings(&["kde"]);
+        let packages = strings(&["plasma"]);
+        let token = confirmations
+            .issue(UPGRADE, &topics, &packages, false, root)
+            .unwrap();
+        // the topic gained a package since the preview.
+        let changed = strings(&["plasma", "kwin"]);
+        assert!(matches!(
+            confirmations.redeem(&token, UPGRADE, &topics, &changed, false, root),
+            Err(SafetyError::InvalidToken)
+        ));
+
+        let token = confirmations
+            .issue(REMOVE, &packages, &[], false, root)
+            .unwrap();
+        assert!(matches!(
+            confirmations.redeem(&token, REMOVE, &packages, &[], true, root),
+            Err(SafetyError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn rejects_other_sysroots() {
+        let mut confirmations = Confirmations::new();
+        let packages = strings(&["vim"]);
+        let token = confirmations
+            .issue(REMOVE, &packages, &[], false, Path::new("/"))
+            .unwrap();
+        // previewed on the host, then switched to a container image.
+        let image = Path::new("/var/lib/machines/buildbot");
+        assert!(matches!(
+            confirmations.redeem(&token, REMOVE, &packages, &[], false, image),
+            Err(SafetyError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn reads_essential_packages_of_a_sysroot() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/rootfs");
+        let essential: Vec<String> = essential_packages(&root).unwrap().into_iter().collect();
+        assert_eq!(essential, ["base-files"]);
+    }
+
+    fn topic(manifest_name: &str, requires_ack: bool) -> TumUpdateInfo {
+        TumUpdateInfo {
+            manifest_name: manifest_name.to_string(),
+            name: Default::default(),
+            is_security: false,
+            package_count: 0,
+            package_names: Vec::new(),
+            caution: None,
+            caution_text: requires_ack.then(|| "Log out first.".to_string()),
+            requires_ack,
+        }
+    }
+
+    #[test]
+    fn requires_acknowledged_cautions() {
+        let topics = [
+            topic("kde", true),
+            topic("rust", false),
+            topic("mesa", true),
+        ];
+        assert!(matches!(
+            check_acknowledged(&topics, &strings(&["kde"])),
+            Err(SafetyError::Unacknowledged(missing)) if missing == ["mesa"]
+        ));
+        check_acknowledged(&topics, &strings(&["kde", "mesa"])).unwrap();
+        // topics without a caution need nothing.
+        check_acknowledged(&topics[1..2], &[]).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use crate::common::apt_worker::APT_WORKER;
+use crate::common::config::SCHEDULE_PATH;
+use crate::common::oma::UpdateSummary;
+use crate::common::polkit::Subject;
+use crate::common::utils::{current_locale, localized};
+use crate::common::{refresh, sysroot};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use systemd::journal;
+use thiserror::Error;
+use zbus::zvariant::Value;
+use zbus::Connection;
+
+/// How often the scheduler wakes up to see whether a check is due.
+const TICK: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum ScheduleError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("toml error: {0}")]
+    Toml(#[from] toml::ser::Error),
+
+    #[error("no config directory for the current user")]
+    NoConfigDir,
+}
+
+/// When to check for updates in the background.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateSchedule {
+    pub enabled: bool,
+    /// Hours between two checks.
+    pub interval_hours: u64,
+    /// Also check on metered connections, refreshing downloads the package lists.
+    pub on_metered: bool,
+}
+
+impl Default for UpdateSchedule {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_hours: 6,
+            on_metered: false,
+        }
+    }
+}
+
+impl UpdateSchedule {
+    /// Load the schedule of the current user, the default if there is none.
+    pub fn load() -> Self {
+        schedule_path()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|s| toml::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), ScheduleError> {
+        let path = schedule_path().ok_or(ScheduleError::NoConfigDir)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_hours.max(1) * 3600)
+    }
+}
+
+fn schedule_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join(SCHEDULE_PATH))
+}
+
+/// Check for updates whenever the schedule says so, forever.
+/// The schedule is reloaded on every tick, so changes apply without a restart.
+pub async fn run_scheduler() {
+    let mut last_check: Option<Instant> = None;
+    // don't notify again about the same updates.
+    let mut notified: Option<String> = None;
+    loop {
+        let schedule = UpdateSchedule::load();
+        let interval = schedule.interval();
+        // a refresh from the GUI counts as a check as well.
+        let due = schedule.enabled
+            && last_check.is_none_or(|t| t.elapsed() >= interval)
+            && refresh::last_refresh()
+                .is_none_or(|t| (Utc::now() - t).to_std().unwrap_or_default() >= interval);
+        if due && (schedule.on_metered || !is_metered().await) {
+            last_check = Some(Instant::now());
+            if let Err(e) = check(&mut notified).await {
+                // LOG_WARNING
+                journal::print(4, &format!("scheduled update check failed: {e}"));
+            }
+        }
+        tokio::time::sleep(TICK).await;
+    }
+}
+
+async fn check(notified: &mut Option<String>) -> anyhow::Result<()> {
+    // a failed refresh still leaves the previous lists to check against.
+    // background checks are about the running system, whatever the GUI manages.
+    // nobody is there to answer a password prompt, so the refresh is skipped
+    // unless polkit allows it without one.
+    let host = sysroot::host();
+    let root = host.clone();
+    let subject = Subject::current()?.non_interactive();
+    tokio::task::spawn_blocking(move || refresh::run_refresh(&root, None, &subject, |_, _| {}))
+        .await?;
+    let summary = APT_WORKER.summary(&host).await?;
+    if summary.count == 0 {
+        *notified = None;
+        return Ok(());
+    }
+    let mut key: Vec<&str> = summary
+        .tum
+        .iter()
+        .map(|t| t.manifest_name.as_str())
+        .collect();
+    key.sort();
+    let key = format!("{}:{}", summary.count, key.join(","));
+    if notified.as_ref() == Some(&key) {
+        return Ok(());
+    }
+    notify(&summary).await?;
+    *notified = Some(key);
+    Ok(())
+}
+
+/// Raise a desktop notification, urgent if any topic is a security update.
+async fn notify(summary: &UpdateSummary) -> zbus::Result<()> {
+    let locale = current_locale();
+    let security: Vec<&str> = summary
+        .tum
+        .iter()
+        .filter(|t| t.is_security)
+        .map(|t| localized(&t.name, &locale).unwrap_or(&t.manifest_name))
+        .collect();
+    let (title, urgency) = if security.is_empty() {
+        ("Updates available", 1u8)
+    } else {
+        ("Security updates available", 2u8)
+    };
+    let mut body = format!("{} packages can be upgraded.", summary.count);
+    for name in &security {
+        body.push_str(&format!("\n• {name}"));
+    }
+
+    let mut hints: HashMap<&str, Value> = HashMap::new();
+    hints.insert("urgency", Value::U8(urgency));
+    hints.insert("desktop-entry", Value::from("aoska"));
+    let conn = Connection::session().await?;
+    conn.call_method(
+        Some("org.freedesktop.Notifications"),
+        "/org/freedesktop/Notifications",
+        Some("org.freedesktop.Notifications"),
+        "Notify",
+        &(
+            "aoska",
+            0u32,
+            "aoska",
+            title,
+            body,
+            Vec::<&str>::new(),
+            hints,
+            -1i32,
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Ask NetworkManager whether the connection is metered, assume not if it can't tell.
+async fn is_metered() -> bool {
+    async fn metered() -> zbus::Result<bool> {
+        let conn = Connection::system().await?;
+        let proxy = zbus::Proxy::new(
+            &conn,
+            "org.freedesktop.NetworkManager",
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+        )
+        .await?;
+        // NMMetered: 1 yes, 3 guess yes.
+        let metered: u32 = proxy.get_property("Metered").await?;
+        Ok(matches!(metered, 1 | 3))
+    }
+    metered().await.unwrap_or(false)
+}
@@ -0,0 +1,66 @@
+use once_cell::sync::Lazy;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use thiserror::Error;
+
+const HOST_SYSROOT: &str = "/";
+
+// The system aoska manages, the running one unless set to a container or chroot image.
+// AOSKA_SYSROOT sets it at startup, e.g. to a fixture rootfs for testing.
+static SYSROOT: Lazy<RwLock<PathBuf>> = Lazy::new(|| {
+    RwLock::new(
+        std::env::var_os("AOSKA_SYSROOT")
+            .map(PathBuf::from)
+            .map(|p| fs::canonicalize(&p).unwrap_or(p))
+            .unwrap_or_else(|| PathBuf::from(HOST_SYSROOT)),
+    )
+});
+
+#[derive(Error, Debug)]
+pub enum SysrootError {
+    #[error("not a directory: {0}")]
+    NotADirectory(PathBuf),
+
+    #[error("no dpkg database in {0}")]
+    NoDpkgDatabase(PathBuf),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub fn current() -> PathBuf {
+    SYSROOT.read().unwrap().clone()
+}
+
+/// Manage the system rooted at `path` from now on, see `validate`.
+pub fn set(path: PathBuf) -> Result<(), SysrootError> {
+    *SYSROOT.write().unwrap() = validate(&path)?;
+    Ok(())
+}
+
+/// Check that `path` is the root of a system with a dpkg database, returns it canonicalized,
+/// so that e.g. "/srv/../" is recognized as the host.
+pub fn validate(path: &Path) -> Result<PathBuf, SysrootError> {
+    if !path.is_dir() {
+        return Err(SysrootError::NotADirectory(path.to_path_buf()));
+    }
+    let path = fs::canonicalize(path)?;
+    if !join(&path, "/var/lib/dpkg/status").is_file() {
+        return Err(SysrootError::NoDpkgDatabase(path));
+    }
+    Ok(path)
+}
+
+pub fn host() -> PathBuf {
+    PathBuf::from(HOST_SYSROOT)
+}
+
+pub fn is_host(sysroot: &Path) -> bool {
+    sysroot == Path::new(HOST_SYSROOT)
+}
+
+/// Where an absolute path of the managed system is, seen from the running one.
+pub fn join(sysroot: &Path, path: &str) -> PathBuf {
+    sysroot.join(path.trim_start_matches('/'))
+}
@@ -1,3 +1,4 @@
+use ahash::HashMap;
 use anyhow::{Context, Ok, Result};
 use reqwest::Client;
 use std::process::Command;
@@ -51,3 +52,57 @@ pub fn run_cmd(mut cmd: Command) -> Result<String> {
         anyhow::bail!(msg)
     }
 }
+
+/// The locale messages are shown in, e.g. "zh_CN", from the usual environment variables.
+pub fn current_locale() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|v| std::env::var(v).ok())
+        .find(|v| !v.is_empty())
+        .map(|v| {
+            // zh_CN.UTF-8@pinyin -> zh_CN
+            v.split(['.', '@']).next().unwrap_or_default().to_string()
+        })
+        .unwrap_or_else(|| "C".to_string())
+}
+
+/// Pick the text for `locale` from a map keyed by locale like "zh_CN",
+/// falling back to the language, then "default", then any text at all.
+pub fn localized<'a>(texts: &'a HashMap<String, String>, locale: &str) -> Option<&'a str> {
+    let lang = locale.split('_').next().unwrap_or(locale);
+    [locale, lang, "default"]
+        .iter()
+        .find_map(|k| texts.get(*k))
+        .or_else(|| texts.iter().min_by_key(|(k, _)| k.as_str()).map(|(_, v)| v))
+        .map(|s| s.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(list: &[(&str, &str)]) -> HashMap<String, String> {
+        list.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn picks_localized_texts() {
+        let t = texts(&[
+            ("default", "Update"),
+            ("zh_CN", "更新"),
+            ("zh", "更新（中文）"),
+        ]);
+        assert_eq!(localized(&t, "zh_CN"), Some("更新"));
+        // same language, other region.
+        assert_eq!(localized(&t, "zh_TW"), Some("更新（中文）"));
+        assert_eq!(localized(&t, "en_US"), Some("Update"));
+        assert_eq!(localized(&t, "C"), Some("Update"));
+
+        // no default: the first by locale, so it doesn't change between runs.
+        let t = texts(&[("ja_JP", "更新プログラム"), ("de_DE", "Aktualisierung")]);
+        assert_eq!(localized(&t, "en_US"), Some("Aktualisierung"));
+        assert_eq!(localized(&HashMap::default(), "en_US"), None);
+    }
+}
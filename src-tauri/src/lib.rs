@@ -14,17 +14,50 @@ pub fn run() {
             command::fetch_tum_update,
             command::fetch_update_count,
             command::fetch_update_detail,
+            command::fetch_update_summary,
             command::get_endpoint_base_url,
+            command::refresh_database,
+            command::last_refresh_time,
+            command::get_update_schedule,
+            command::set_update_schedule,
+            command::get_sysroot,
+            command::set_sysroot,
+            command::list_held_packages,
+            command::hold_packages,
+            command::unhold_packages,
+            command::preview_install,
+            command::preview_remove,
             command::start_upgrade,
+            command::preview_topic_upgrade,
+            command::start_topic_upgrade,
+            command::preview_security_upgrade,
             command::start_install,
             command::oma_is_busy,
+            command::oma_lock_holder,
             command::start_remove,
+            command::list_task_history,
+            command::task_history_detail,
+            command::list_oma_units,
             command::oma_unit_status,
             command::oma_unit_logs,
             command::follow_oma_logs,
             command::stop_follow_oma_logs,
+            command::oma_log_buffer,
+            command::cancel_oma_unit,
             command::oma_unit_result,
+            command::take_launch_deb,
+            command::inspect_local_deb,
+            command::import_local_deb,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+/// Run the backend as a D-Bus service on the session bus instead of the GUI,
+/// checking for updates in the background.
+pub fn run_dbus() {
+    let rt = tokio::runtime::Runtime::new().expect("tokio runtime");
+    rt.spawn(common::scheduler::run_scheduler());
+    rt.block_on(common::dbus::run_session_service())
+        .expect("error while running the D-Bus service");
+}
@@ -1,4 +1,8 @@
 
 fn main() {
-    aoska::run();
+    if std::env::args().any(|a| a == "--dbus") {
+        aoska::run_dbus();
+    } else {
+        aoska::run();
+    }
 }
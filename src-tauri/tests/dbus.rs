@@ -0,0 +1,125 @@
+use aoska::common::dbus::{serve, BUS_NAME, OBJECT_PATH};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use zbus::connection::Builder;
+
+// A private session bus, killed on drop.
+struct PrivateBus {
+    daemon: Child,
+    address: String,
+}
+
+impl PrivateBus {
+    fn start() -> Option<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some(Self {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+#[tokio::test]
+async fn serve_on_private_bus() {
+    let Some(bus) = PrivateBus::start() else {
+        eprintln!("dbus-daemon not available, skipping");
+        return;
+    };
+    let _service = serve(Builder::address(bus.address.as_str()).unwrap())
+        .await
+        .unwrap();
+    let client = Builder::address(bus.address.as_str())
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let reply = client
+        .call_method(Some(BUS_NAME), OBJECT_PATH, Some(BUS_NAME), "IsBusy", &())
+        .await
+        .unwrap();
+    let _busy: bool = reply.body().deserialize().unwrap();
+
+    let err = client
+        .call_method(
+            Some(BUS_NAME),
+            OBJECT_PATH,
+            Some(BUS_NAME),
+            "Install",
+            &(Vec::<String>::new(),),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, zbus::Error::MethodError(name, _, _)
+        if name.as_str() == "org.freedesktop.DBus.Error.InvalidArgs"));
+}
+
+// polkit must be asked about the process calling the service, not the service itself.
+#[tokio::test]
+async fn authorizes_the_caller() {
+    let Some(bus) = PrivateBus::start() else {
+        eprintln!("dbus-daemon not available, skipping");
+        return;
+    };
+    // a pkcheck that records its arguments and refuses.
+    let dir = std::env::temp_dir().join(format!("aoska-pkcheck-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let args_file = dir.join("args");
+    let pkcheck = dir.join("pkcheck");
+    fs::write(
+        &pkcheck,
+        format!("#!/bin/sh\necho \"$@\" > {}\nexit 2\n", args_file.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&pkcheck, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var("PATH", format!("{}:{path}", dir.display()));
+
+    let _service = serve(Builder::address(bus.address.as_str()).unwrap())
+        .await
+        .unwrap();
+    // call from another process, so the caller and the service differ.
+    let Ok(client) = Command::new("dbus-send")
+        .arg(format!("--bus={}", bus.address))
+        .arg("--print-reply")
+        .arg(format!("--dest={BUS_NAME}"))
+        .arg(OBJECT_PATH)
+        .arg(format!("{BUS_NAME}.Install"))
+        .arg("array:string:vim")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    else {
+        eprintln!("dbus-send not available, skipping");
+        return;
+    };
+    let client_pid = client.id();
+    let out = tokio::task::spawn_blocking(move || client.wait_with_output())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(
+        String::from_utf8_lossy(&out.stderr).contains("org.freedesktop.DBus.Error.AccessDenied")
+    );
+    let args = fs::read_to_string(&args_file).unwrap();
+    assert!(args.contains(&format!("--process {client_pid},")), "{args}");
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,61 @@
+use aoska::common::hold::held_packages;
+use aoska::common::oma::{check_upgrade, new_apt};
+use aoska::common::sysroot::{self, SysrootError};
+use std::fs;
+use std::path::PathBuf;
+
+// A tiny AOSC OS image: foo and bar have an update, bar is on hold.
+fn fixture() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/rootfs")
+}
+
+#[test]
+fn switches_to_the_fixture() {
+    let root = fs::canonicalize(fixture()).unwrap();
+    sysroot::set(fixture().join("var/..")).unwrap();
+    assert_eq!(sysroot::current(), root);
+    assert!(!sysroot::is_host(&sysroot::current()));
+    assert!(sysroot::join(&root, "/var/lib/dpkg/status").is_file());
+
+    assert!(matches!(
+        sysroot::set(fixture().join("var")),
+        Err(SysrootError::NoDpkgDatabase(_))
+    ));
+    assert!(matches!(
+        sysroot::set(fixture().join("var/lib/dpkg/status")),
+        Err(SysrootError::NotADirectory(_))
+    ));
+    // a failed switch keeps the previous sysroot.
+    assert_eq!(sysroot::current(), root);
+}
+
+#[test]
+fn validates_sysroots() {
+    let root = fs::canonicalize(fixture()).unwrap();
+    assert_eq!(sysroot::validate(&fixture().join("var/..")).unwrap(), root);
+    assert!(matches!(
+        sysroot::validate(&fixture().join("var")),
+        Err(SysrootError::NoDpkgDatabase(_))
+    ));
+    assert!(matches!(
+        sysroot::validate(&fixture().join("missing")),
+        Err(SysrootError::NotADirectory(_))
+    ));
+}
+
+#[test]
+fn reads_holds_of_the_fixture() {
+    let held: Vec<String> = held_packages(&fixture()).unwrap().into_iter().collect();
+    assert_eq!(held, ["bar"]);
+}
+
+#[tokio::test]
+async fn checks_updates_of_the_fixture() {
+    let root = fixture();
+    let apt = new_apt(&root, true).unwrap();
+    let (count, operation, held) = check_upgrade(&apt, &root).await.unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(held, ["bar"]);
+    let upgraded: Vec<&str> = operation.install.iter().map(|p| p.name()).collect();
+    assert_eq!(upgraded, ["foo"]);
+}